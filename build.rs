@@ -3,7 +3,7 @@ use std::process::Command;
 fn main() {
     // Set build time
     let output = Command::new("date")
-        .args(&["+%Y-%m-%d %H:%M:%S UTC"])
+        .args(["+%Y-%m-%d %H:%M:%S UTC"])
         .output();
     
     let build_time = match output {
@@ -0,0 +1,331 @@
+use crate::body::{self, NoxBody};
+use crate::config::MockRoute;
+use crate::context::RequestContext;
+use crate::journal::RequestQuery;
+use crate::router::{MockRouter, ScenarioError};
+
+#[cfg(feature = "proxy")]
+use crate::circuit::CircuitState;
+use hyper::{Method, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+// Runtime control endpoints live under this prefix and are never matched
+// against mock routes
+pub const ADMIN_PREFIX: &str = "/__nox";
+
+#[derive(Debug, Deserialize)]
+struct StateUpdate {
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Verification {
+    #[serde(flatten)]
+    request: RequestQuery,
+    times: Option<usize>,
+    at_least: Option<usize>,
+    at_most: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Activation {
+    group: String,
+    scenario: String,
+}
+
+pub fn is_admin_path(path: &str) -> bool {
+    path.strip_prefix(ADMIN_PREFIX).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+pub fn handle(router: &MockRouter, ctx: &RequestContext) -> Response<NoxBody> {
+    let path = ctx.path()[ADMIN_PREFIX.len()..].trim_matches('/');
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match (&ctx.method, segments.as_slice()) {
+        (&Method::GET, ["scenarios"]) => json_response(StatusCode::OK, json!(router.scenarios())),
+        (&Method::POST, ["scenarios", "reset"]) => {
+            let _ = router.reset_scenario_states(None);
+            router.reset_sequences(None);
+            json_response(StatusCode::OK, json!(router.scenarios()))
+        }
+        (&Method::POST, ["scenarios", "activate"]) => {
+            let activation: Activation = match parse_body(ctx) {
+                Ok(activation) => activation,
+                Err(err) => return error_response(StatusCode::BAD_REQUEST, &err),
+            };
+            let result = router.activate_scenario(&activation.group, &activation.scenario);
+            scenario_result(router, &activation.scenario, result)
+        }
+        (&Method::GET, ["scenarios", name]) => scenario_result(router, name, Ok(())),
+        (&Method::POST, ["scenarios", name, "reset"]) => {
+            let result = router.reset_scenario_states(Some(name));
+            if result.is_ok() {
+                router.reset_sequences(Some(name));
+            }
+            scenario_result(router, name, result)
+        }
+        (&Method::POST, ["scenarios", name, "enable"]) => {
+            scenario_result(router, name, router.set_scenario_enabled(name, true))
+        }
+        (&Method::POST, ["scenarios", name, "disable"]) => {
+            scenario_result(router, name, router.set_scenario_enabled(name, false))
+        }
+        (&Method::PUT, ["scenarios", name, "state"]) => {
+            let update: StateUpdate = match parse_body(ctx) {
+                Ok(update) => update,
+                Err(err) => return error_response(StatusCode::BAD_REQUEST, &err),
+            };
+            scenario_result(router, name, router.set_scenario_state(name, &update.state))
+        }
+        (&Method::GET, ["routes"]) => json_response(StatusCode::OK, json!(router.routes())),
+        (&Method::POST, ["routes"]) => {
+            let route: MockRoute = match parse_body(ctx) {
+                Ok(route) => route,
+                Err(err) => return error_response(StatusCode::BAD_REQUEST, &err),
+            };
+            match router.add_runtime_route(&route) {
+                Ok(info) => json_response(StatusCode::CREATED, json!(info)),
+                Err(err) => error_response(StatusCode::BAD_REQUEST, &err),
+            }
+        }
+        // Only clears routes added through this API; config routes stay put
+        (&Method::DELETE, ["routes"]) => {
+            json_response(StatusCode::OK, json!({ "removed": router.clear_runtime_routes() }))
+        }
+        (&Method::GET, ["routes", id]) => match router.routes().into_iter().find(|r| r.id == *id) {
+            Some(info) => json_response(StatusCode::OK, json!(info)),
+            None => unknown_route(id),
+        },
+        (&Method::DELETE, ["routes", id]) => {
+            if router.remove_route(id) {
+                json_response(StatusCode::OK, json!({ "removed": 1 }))
+            } else {
+                unknown_route(id)
+            }
+        }
+        (&Method::GET, ["requests"]) => {
+            let query = RequestQuery {
+                route: ctx.query_values("route").next().map(str::to_string),
+                method: ctx.query_values("method").next().map(str::to_string),
+                path: ctx.query_values("path").next().map(str::to_string),
+                listener: ctx.query_values("listener").next().map(str::to_string),
+                ..Default::default()
+            };
+            let limit = ctx.query_values("limit").next().and_then(|l| l.parse().ok());
+            find_requests(router, &query, limit)
+        }
+        (&Method::POST, ["requests", "find"]) => match parse_body::<RequestQuery>(ctx) {
+            Ok(query) => find_requests(router, &query, None),
+            Err(err) => error_response(StatusCode::BAD_REQUEST, &err),
+        },
+        (&Method::DELETE, ["requests"]) => {
+            router.journal().clear();
+            json_response(StatusCode::OK, json!({ "cleared": true }))
+        }
+        (&Method::POST, ["verify"]) => match parse_body::<Verification>(ctx) {
+            Ok(verification) => verify(router, &verification),
+            Err(err) => error_response(StatusCode::BAD_REQUEST, &err),
+        },
+        (&Method::GET, ["upstreams"]) => upstreams(router),
+        (&Method::GET, ["health"]) => health(router),
+        _ => error_response(StatusCode::NOT_FOUND, "unknown admin endpoint"),
+    }
+}
+
+fn find_requests(router: &MockRouter, query: &RequestQuery, limit: Option<usize>) -> Response<NoxBody> {
+    let filter = match query.compile() {
+        Ok(filter) => filter,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, &err),
+    };
+
+    let found = router.journal().find(&filter);
+    // A limit keeps the most recent requests
+    let skip = limit.map_or(0, |limit| found.len().saturating_sub(limit));
+    let requests: Vec<Value> = found.iter().skip(skip).map(|r| r.to_json()).collect();
+    json_response(StatusCode::OK, Value::Array(requests))
+}
+
+// Answers 200 when the journal holds the expected number of matching requests
+// and 417 otherwise, so a client test can simply check the status
+fn verify(router: &MockRouter, verification: &Verification) -> Response<NoxBody> {
+    let filter = match verification.request.compile() {
+        Ok(filter) => filter,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, &err),
+    };
+
+    let (at_least, at_most) = match verification.times {
+        Some(times) => (times, Some(times)),
+        None => (verification.at_least.unwrap_or(1), verification.at_most),
+    };
+    let expected = match at_most {
+        Some(max) if max == at_least => format!("exactly {}", at_least),
+        Some(max) => format!("between {} and {}", at_least, max),
+        None => format!("at least {}", at_least),
+    };
+
+    let found = router.journal().find(&filter);
+    let count = found.len();
+    let verified = count >= at_least && at_most.is_none_or(|max| count <= max);
+    let status = if verified { StatusCode::OK } else { StatusCode::EXPECTATION_FAILED };
+
+    json_response(
+        status,
+        json!({
+            "verified": verified,
+            "count": count,
+            "expected": expected,
+            "requests": found.iter().map(|r| r.id).collect::<Vec<_>>(),
+        }),
+    )
+}
+
+#[cfg(feature = "proxy")]
+fn upstreams(router: &MockRouter) -> Response<NoxBody> {
+    let stats = router.proxy().map(|proxy| proxy.stats()).unwrap_or_default();
+    json_response(StatusCode::OK, json!(stats))
+}
+
+#[cfg(not(feature = "proxy"))]
+fn upstreams(_router: &MockRouter) -> Response<NoxBody> {
+    json_response(StatusCode::OK, json!([]))
+}
+
+// The server is "degraded" while any upstream is out of rotation: failing its
+// health check, ejected, or behind an open circuit
+#[cfg(feature = "proxy")]
+fn health(router: &MockRouter) -> Response<NoxBody> {
+    let pools = router.proxy().map(|proxy| proxy.stats()).unwrap_or_default();
+    let mut degraded = false;
+    let mut upstreams = Vec::new();
+    for pool in &pools {
+        for upstream in &pool.upstreams {
+            let available = upstream.healthy && !upstream.ejected && upstream.circuit != Some(CircuitState::Open);
+            degraded |= !available;
+            upstreams.push(json!({
+                "prefix": pool.prefix,
+                "name": upstream.name,
+                "available": available,
+                "healthy": upstream.healthy,
+                "ejected": upstream.ejected,
+                "circuit": upstream.circuit,
+            }));
+        }
+    }
+
+    let status = if degraded { "degraded" } else { "ok" };
+    json_response(StatusCode::OK, json!({ "status": status, "upstreams": upstreams }))
+}
+
+#[cfg(not(feature = "proxy"))]
+fn health(_router: &MockRouter) -> Response<NoxBody> {
+    json_response(StatusCode::OK, json!({ "status": "ok", "upstreams": [] }))
+}
+
+fn parse_body<T: DeserializeOwned>(ctx: &RequestContext) -> Result<T, String> {
+    serde_json::from_slice(&ctx.body).map_err(|e| format!("invalid request body: {}", e))
+}
+
+// Responds with the scenario as it stands after the change, or with the reason
+// the change was refused
+fn scenario_result(router: &MockRouter, name: &str, result: Result<(), ScenarioError>) -> Response<NoxBody> {
+    let status = match result {
+        Ok(()) => router.scenario(name),
+        Err(ScenarioError::UnknownGroup) => {
+            return error_response(StatusCode::NOT_FOUND, "unknown scenario group");
+        }
+        Err(ScenarioError::NotInGroup) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("scenario '{}' is not in that group", name),
+            );
+        }
+        Err(ScenarioError::UnknownScenario) => None,
+    };
+
+    match status {
+        Some(status) => json_response(StatusCode::OK, json!(status)),
+        None => error_response(StatusCode::NOT_FOUND, &format!("unknown scenario '{}'", name)),
+    }
+}
+
+fn unknown_route(id: &str) -> Response<NoxBody> {
+    error_response(StatusCode::NOT_FOUND, &format!("unknown route '{}'", id))
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<NoxBody> {
+    json_response(status, json!({ "error": message }))
+}
+
+fn json_response(status: StatusCode, value: Value) -> Response<NoxBody> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body::full(value.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::HeaderMap;
+    use http_body_util::BodyExt;
+
+    async fn call(router: &MockRouter, method: Method, path: &str, body: &str) -> (StatusCode, Value) {
+        let ctx = RequestContext::new(
+            method,
+            path.parse().unwrap(),
+            HeaderMap::new(),
+            Bytes::from(body.to_string()),
+        );
+        let response = handle(router, &ctx);
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_runtime_route_lifecycle() {
+        let router = MockRouter::new();
+        let route = r#"{"id": "pay", "path": "/pay", "method": "POST", "response": {"status": 503}}"#;
+
+        let (status, info) = call(&router, Method::POST, "/__nox/routes", route).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(info["id"], "pay");
+        assert_eq!(info["source"], "runtime");
+        let (status, _) = call(&router, Method::POST, "/__nox/routes", route).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, info) = call(&router, Method::GET, "/__nox/routes/pay", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(info["path"], "/pay");
+        let (_, routes) = call(&router, Method::GET, "/__nox/routes", "").await;
+        assert!(routes.as_array().unwrap().iter().any(|r| r["id"] == "pay"));
+
+        let (status, removed) = call(&router, Method::DELETE, "/__nox/routes/pay", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(removed["removed"], 1);
+        let (status, _) = call(&router, Method::GET, "/__nox/routes/pay", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&router, Method::DELETE, "/__nox/routes/pay", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_clearing_routes_keeps_builtin_ones() {
+        let router = MockRouter::new();
+        let (_, before) = call(&router, Method::GET, "/__nox/routes", "").await;
+        for path in ["/a", "/b"] {
+            let route = json!({ "path": path, "method": "GET", "response": { "status": 200 } }).to_string();
+            call(&router, Method::POST, "/__nox/routes", &route).await;
+        }
+
+        let (status, removed) = call(&router, Method::DELETE, "/__nox/routes", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(removed["removed"], 2);
+        let (_, after) = call(&router, Method::GET, "/__nox/routes", "").await;
+        assert_eq!(after, before);
+    }
+}
@@ -0,0 +1,87 @@
+use bytes::Bytes;
+use futures::{stream, TryStreamExt};
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::Frame;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+// Response body type used throughout the server; boxed so a response can be
+// a single buffer, a throttled trickle or a proxied stream
+pub type NoxBody = http_body_util::combinators::BoxBody<Bytes, std::io::Error>;
+
+// Chunks go out this often when the rate allows at least a byte per chunk
+const THROTTLE_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn full(bytes: impl Into<Bytes>) -> NoxBody {
+    Full::new(bytes.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+pub fn empty() -> NoxBody {
+    full(Bytes::new())
+}
+
+// Sends `bytes` at roughly `bytes_per_second`, one chunk every 100ms, or one
+// byte at a time for rates too low to fill a chunk that often
+pub fn throttled(bytes: Bytes, bytes_per_second: u64) -> NoxBody {
+    let (chunk_size, interval) = throttle_chunks(bytes_per_second);
+
+    let chunks = stream::unfold((bytes, true), move |(mut remaining, first)| async move {
+        if remaining.is_empty() {
+            return None;
+        }
+        if !first {
+            tokio::time::sleep(interval).await;
+        }
+        let chunk = remaining.split_to(chunk_size.min(remaining.len()));
+        Some((Ok(Frame::data(chunk)), (remaining, false)))
+    });
+
+    StreamBody::new(chunks).boxed()
+}
+
+fn throttle_chunks(bytes_per_second: u64) -> (usize, Duration) {
+    let bytes_per_second = bytes_per_second.max(1);
+    let per_interval = bytes_per_second * THROTTLE_INTERVAL.as_millis() as u64 / 1000;
+    if per_interval >= 1 {
+        return (per_interval as usize, THROTTLE_INTERVAL);
+    }
+    (1, Duration::from_secs_f64(1.0 / bytes_per_second as f64))
+}
+
+// Streams `len` bytes of a file starting at `offset`, so large files are never
+// held in memory
+pub async fn file(path: &Path, offset: u64, len: u64) -> std::io::Result<NoxBody> {
+    let mut file = tokio::fs::File::open(path).await?;
+    if offset > 0 {
+        file.seek(SeekFrom::Start(offset)).await?;
+    }
+    let chunks = ReaderStream::new(file.take(len)).map_ok(Frame::data);
+    Ok(StreamBody::new(chunks).boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_chunks_follow_the_rate() {
+        assert_eq!(throttle_chunks(2048), (204, THROTTLE_INTERVAL));
+        assert_eq!(throttle_chunks(10), (1, THROTTLE_INTERVAL));
+        assert_eq!(throttle_chunks(4), (1, Duration::from_millis(250)));
+        assert_eq!(throttle_chunks(1), (1, Duration::from_secs(1)));
+        // Zero is treated as the slowest rate rather than dividing by it
+        assert_eq!(throttle_chunks(0), (1, Duration::from_secs(1)));
+    }
+
+    #[tokio::test]
+    async fn test_throttled_body_is_complete() {
+        let body = throttled(Bytes::from_static(b"0123456789"), 100);
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected, Bytes::from_static(b"0123456789"));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::config::CircuitBreakerConfig;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_OPEN_TIMEOUT: u64 = 30;
+
+// Stops sending requests to an upstream that keeps failing, and later lets a
+// few trial requests through to find out whether it has recovered
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    failure_threshold: u32,
+    open_timeout: Duration,
+    half_open_requests: u32,
+    success_threshold: u32,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { since: Instant, in_flight: u32, successes: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, config: &CircuitBreakerConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            failure_threshold: config.failure_threshold.unwrap_or(DEFAULT_FAILURE_THRESHOLD).max(1),
+            open_timeout: Duration::from_secs(config.open_timeout.unwrap_or(DEFAULT_OPEN_TIMEOUT)),
+            half_open_requests: config.half_open_requests.unwrap_or(1).max(1),
+            success_threshold: config.success_threshold.unwrap_or(1).max(1),
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    // Whether a request could be let through right now, without taking a
+    // trial slot
+    pub fn permits(&self) -> bool {
+        let now = Instant::now();
+        match *self.state.lock().unwrap() {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } => now >= until,
+            BreakerState::HalfOpen { since, in_flight, .. } => {
+                in_flight < self.half_open_requests || self.trials_lost(since, now)
+            }
+        }
+    }
+
+    // Lets a request through, taking a trial slot when half open
+    pub fn acquire(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now >= until => {
+                self.transition(&mut state, BreakerState::HalfOpen { since: now, in_flight: 1, successes: 0 });
+                true
+            }
+            BreakerState::Open { .. } => false,
+            BreakerState::HalfOpen { since, in_flight, successes } => {
+                if self.trials_lost(since, now) {
+                    *state = BreakerState::HalfOpen { since: now, in_flight: 1, successes };
+                    true
+                } else if in_flight < self.half_open_requests {
+                    *state = BreakerState::HalfOpen { since, in_flight: in_flight + 1, successes };
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => *state = BreakerState::Closed { failures: 0 },
+            BreakerState::HalfOpen { since, in_flight, successes } => {
+                if successes + 1 >= self.success_threshold {
+                    self.transition(&mut state, BreakerState::Closed { failures: 0 });
+                } else {
+                    *state = BreakerState::HalfOpen {
+                        since,
+                        in_flight: in_flight.saturating_sub(1),
+                        successes: successes + 1,
+                    };
+                }
+            }
+            BreakerState::Open { .. } => {}
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let open = BreakerState::Open { until: Instant::now() + self.open_timeout };
+        match *state {
+            BreakerState::Closed { failures } if failures + 1 >= self.failure_threshold => {
+                self.transition(&mut state, open);
+            }
+            BreakerState::Closed { failures } => *state = BreakerState::Closed { failures: failures + 1 },
+            // A single failed trial is enough to open again
+            BreakerState::HalfOpen { .. } => self.transition(&mut state, open),
+            BreakerState::Open { .. } => {}
+        }
+    }
+
+    // An open breaker whose timeout has passed reports as half open, since the
+    // next request will be a trial
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            BreakerState::Closed { .. } => CircuitState::Closed,
+            BreakerState::Open { until } if Instant::now() < until => CircuitState::Open,
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    // Trials that never reported back (the client went away mid-request) would
+    // otherwise hold the breaker half open forever
+    fn trials_lost(&self, since: Instant, now: Instant) -> bool {
+        now.duration_since(since) >= self.open_timeout
+    }
+
+    fn transition(&self, state: &mut BreakerState, next: BreakerState) {
+        let label = match next {
+            BreakerState::Closed { .. } => "closed",
+            BreakerState::Open { .. } => "open",
+            BreakerState::HalfOpen { .. } => "half open",
+        };
+        println!("Circuit for upstream {} is {}", self.name, label);
+        *state = next;
+    }
+}
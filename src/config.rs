@@ -1,41 +1,323 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NoxConfig {
     pub server: ServerConfig,
     pub mock: Option<MockConfig>,
+    pub proxy: Option<ProxyConfig>,
+    pub static_files: Option<Vec<StaticFilesConfig>>,
+    pub seed: Option<u64>, // fixes random choices so a run can be replayed
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    pub http2: Option<Http2Config>,
+    pub tls: Option<TlsConfig>,
+    pub listeners: Option<Vec<ListenerConfig>>, // served alongside host/port, which is named "default"
+    pub drain_timeout: Option<u64>, // seconds in-flight requests get to finish on shutdown, defaults to 10
+}
+
+// An extra socket to accept connections on: a TCP port, or a Unix domain
+// socket with `unix`. Scenarios can be limited to listeners by name
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ListenerConfig {
+    pub name: String,
+    pub host: Option<String>, // defaults to server.host
+    pub port: Option<u16>,
+    pub unix: Option<PathBuf>, // socket path, relative to the config file
+    pub tls: Option<TlsConfig>, // plain HTTP unless set
+}
+
+// Either a PEM certificate and key, or `self_signed` for a throwaway CA and
+// certificate generated at startup
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TlsConfig {
+    pub cert_path: Option<PathBuf>, // PEM certificate chain, relative to the config file
+    pub key_path: Option<PathBuf>, // PEM private key
+    pub self_signed: Option<bool>,
+    pub ca_output: Option<PathBuf>, // where the generated CA is written, defaults to nox-ca.pem
+    pub client_ca_path: Option<PathBuf>, // verify client certificates against this CA (mTLS)
+    pub client_auth_optional: Option<bool>, // also accept clients without a certificate
+    pub names: Option<Vec<String>>, // extra names the self-signed certificate is valid for
+}
+
+// HTTP/1.1 and HTTP/2 (h2c with prior knowledge) are served on the same port
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Http2Config {
+    pub enabled: Option<bool>, // false serves HTTP/1.1 only, defaults to true
+    pub max_concurrent_streams: Option<u32>, // per connection, defaults to 200
+    pub keep_alive_interval: Option<u64>, // seconds between pings, off by default
+    pub keep_alive_timeout: Option<u64>, // seconds to wait for a ping ack, defaults to 20
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MockConfig {
     pub scenarios: Vec<MockScenario>,
+    pub max_body_size: Option<usize>, // bytes
+    pub default_delay: Option<DelaySpec>, // used by routes without their own delay
+    pub journal_size: Option<usize>, // requests kept for /__nox/requests, 0 disables
+    pub journal_body_limit: Option<usize>, // body bytes kept per journaled request, defaults to 64 KiB
+    pub verbose_not_found: Option<bool>, // explain unmatched requests, defaults to true
+    pub record: Option<RecordConfig>, // forward unmatched requests and capture them
+    pub fallback_upstream: Option<String>, // base URL unmatched requests pass through to
+}
+
+// Serves the files under `root_dir` at `prefix` for requests no mock route matched
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StaticFilesConfig {
+    pub prefix: Option<String>, // defaults to "/"
+    pub root_dir: PathBuf, // relative to the config file
+    pub index_files: Option<Vec<String>>, // tried in order for directories, defaults to index.html
+    pub directory_listing: Option<bool>, // list directories without an index file, defaults to false
+    pub cache_control: Option<String>, // for files no cache rule matches
+    pub cache_rules: Option<Vec<CacheRule>>, // first matching glob wins
+    pub spa_fallback: Option<String>, // file served for unknown paths without an extension
+    pub precompressed: Option<bool>, // serve .br/.gz siblings the client accepts, defaults to false
+    pub dev: Option<bool>, // send no-cache headers on everything, defaults to false
+}
+
+// Globs without a slash match the file name at any depth, others match the
+// path from root_dir; `*` stays within a segment and `**` crosses them
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CacheRule {
+    pub pattern: String,
+    pub cache_control: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProxyConfig {
+    pub routes: Vec<ProxyRoute>,
+    pub timeout: Option<u64>, // seconds to connect and to get response headers
+    pub health_check_interval: Option<u64>, // seconds between active checks
+    pub max_fails: Option<u32>, // consecutive errors before an upstream is ejected
+    pub fail_timeout: Option<u64>, // seconds an ejected upstream is left out
+    pub retry: Option<RetryConfig>, // default for upstreams without their own
+    pub circuit_breaker: Option<CircuitBreakerConfig>, // default for upstreams without their own
+}
+
+// Requests under `prefix` that no mock route matched go to one of `upstreams`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProxyRoute {
+    pub prefix: String,
+    pub rewrite: Option<String>, // replaces the prefix upstream, defaults to the prefix
+    pub strategy: Option<LoadBalancingStrategy>,
+    pub upstreams: Vec<UpstreamConfig>,
+    pub transform: Option<Vec<TransformRule>>, // applied in order to every upstream response
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpstreamConfig {
+    pub name: String,
+    pub url: String,
+    pub weight: Option<u32>,
+    pub health_check: Option<String>, // path probed by active health checks
+    pub retry: Option<RetryConfig>,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+// Failed requests (connection errors, timeouts and `retry_on` statuses) are
+// tried again, against whichever upstream the pool picks next
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RetryConfig {
+    pub attempts: Option<u32>, // retries after the first try, defaults to 2
+    pub backoff: Option<u64>, // milliseconds before the first retry, doubled each time
+    pub max_backoff: Option<u64>, // milliseconds, caps the doubling
+    pub per_try_timeout: Option<u64>, // milliseconds to wait for response headers
+    pub retry_on: Option<Vec<u16>>, // statuses worth retrying, defaults to 502, 503 and 504
+    pub non_idempotent: Option<bool>, // also retry POST and PATCH, defaults to false
+}
+
+// Opens after `failure_threshold` failures in a row and rejects requests for
+// `open_timeout`, then lets `half_open_requests` through to decide whether to
+// close again
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: Option<u32>, // defaults to 5
+    pub open_timeout: Option<u64>, // seconds, defaults to 30
+    pub half_open_requests: Option<u32>, // trial requests at once, defaults to 1
+    pub success_threshold: Option<u32>, // trial successes needed to close, defaults to 1
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancingStrategy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+    WeightedRoundRobin,
+    Random,
+}
+
+// Header and status rules leave the body streaming; JSON and regex rules
+// need the whole body first
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TransformRule {
+    SetHeader { name: String, value: String },
+    RemoveHeader { name: String },
+    Status { status: u16 },
+    SetJson { path: String, value: serde_json::Value },
+    RemoveJson { path: String },
+    Replace { pattern: String, with: String }, // regex, `with` may use $1 for groups
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecordConfig {
+    pub upstream: String, // base URL unmatched requests are forwarded to
+    pub output: PathBuf, // YAML file the captured scenario is written to
+    pub scenario: Option<String>, // defaults to "recorded"
+    pub strip_headers: Option<Vec<String>>, // response headers left out of captures
+    pub match_headers: Option<Vec<String>>, // request headers captured as matchers
+    pub templatize_ids: Option<bool>, // defaults to true
+    pub id_pattern: Option<String>, // regex for path segments treated as ids
+    pub dedupe: Option<DedupeMode>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupeMode {
+    // Identical calls keep the first response seen
+    #[default]
+    KeepFirst,
+    KeepLast,
+    // Identical calls become a response sequence
+    Sequence,
+    // Every call is captured as its own route
+    Off,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MockScenario {
     pub name: String,
+    pub initial_state: Option<String>, // defaults to "Started"
+    pub enabled: Option<bool>, // defaults to true
+    pub group: Option<String>, // at most one scenario per group is enabled
+    pub hosts: Option<Vec<String>>, // only serve these Host names, "*.example.com" matches subdomains
+    pub listeners: Option<Vec<String>>, // only serve requests arriving on these listeners
     pub routes: Vec<MockRoute>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct MockRoute {
+    pub id: Option<String>, // generated when not set
     pub path: String,
     pub method: String,
+    pub headers: Option<HashMap<String, ValueMatcher>>,
+    pub query: Option<HashMap<String, ValueMatcher>>,
+    pub body: Option<BodyMatcher>,
+    pub response: Option<MockResponse>,
+    pub responses: Option<Vec<WeightedResponse>>, // picked at random by weight
+    pub sequence: Option<Vec<MockResponse>>, // returned in order, one per call
+    pub sequence_mode: Option<SequenceMode>,
+    pub required_state: Option<String>, // only match while the scenario is in this state
+    pub scenario: Option<String>, // runtime routes only: the scenario the route belongs to
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SequenceMode {
+    #[default]
+    StopAtLast, // keep returning the last response
+    Cycle,      // start over from the first response
+    #[serde(rename = "then-404")]
+    Then404,    // respond 404 once the sequence is used up
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WeightedResponse {
+    pub weight: u32,
     pub response: MockResponse,
 }
 
+impl MockRoute {
+    pub fn responses_mut(&mut self) -> impl Iterator<Item = &mut MockResponse> {
+        self.response
+            .iter_mut()
+            .chain(self.responses.iter_mut().flatten().map(|w| &mut w.response))
+            .chain(self.sequence.iter_mut().flatten())
+    }
+}
+
+// A header, query or form value condition: either a plain value that must
+// match exactly, or a rule such as `{ regex: "^v[0-9]+$" }` or `{ present: false }`
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ValueMatcher {
+    Rule(ValueRule),
+    Exact(serde_json::Value),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValueRule {
+    pub equals: Option<String>,
+    pub contains: Option<String>,
+    pub regex: Option<String>,
+    pub present: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BodyMatcher {
+    pub equals: Option<String>,
+    pub contains: Option<String>,
+    pub regex: Option<String>,
+    pub json_path: Option<HashMap<String, serde_json::Value>>,
+    pub json_partial: Option<serde_json::Value>,
+    pub form: Option<HashMap<String, ValueMatcher>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct MockResponse {
     pub status: u16,
     pub headers: Option<HashMap<String, String>>,
+    #[serde(default)]
     pub body: String,
+    pub body_file: Option<PathBuf>, // relative to the config file, may use {param} placeholders
+    pub body_json: Option<serde_json::Value>,
+    pub template: Option<bool>,
+    pub delay: Option<DelaySpec>,
+    pub jitter: Option<u64>, // milliseconds of uniform random delay added on top
+    pub bandwidth: Option<u64>, // bytes per second
+    pub fault: Option<Fault>,
+    pub new_state: Option<String>, // move the route's scenario to this state once sent
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    EmptyResponse,      // close the socket without sending anything
+    ConnectionReset,    // send part of the body, then reset the connection
+    TruncatedBody,      // full Content-Length but only half the body
+    MalformedResponse,  // random bytes instead of an HTTP response
+    Hang,               // never respond
+}
+
+// Delay before a response is sent, in milliseconds:
+//   delay: 250
+//   delay: { min: 100, max: 300 }
+//   delay: { distribution: pareto, p50: 80, p99: 1200 }
+// Samples from the p50/p99 distributions are capped at `max`, which defaults
+// to 10 times p99
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum DelaySpec {
+    Fixed(u64),
+    Range { min: u64, max: u64 },
+    Distribution(LatencyDistribution),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "distribution", rename_all = "lowercase")]
+pub enum LatencyDistribution {
+    Uniform { min: u64, max: u64 },
+    Normal { p50: u64, p99: u64, max: Option<u64> },
+    LogNormal { p50: u64, p99: u64, max: Option<u64> },
+    Pareto { p50: u64, p99: u64, max: Option<u64> },
 }
 
 impl Default for NoxConfig {
@@ -44,8 +326,15 @@ impl Default for NoxConfig {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
                 port: 3000,
+                http2: None,
+                tls: None,
+                listeners: None,
+                drain_timeout: None,
             },
             mock: None,
+            proxy: None,
+            static_files: None,
+            seed: None,
         }
     }
 }
@@ -57,6 +346,72 @@ impl NoxConfig {
 
     pub fn load_from_file(path: &str) -> crate::Result<Self> {
         let content = std::fs::read_to_string(path)?;
-        Ok(Self::from_yaml(&content)?)
+        let mut config = Self::from_yaml(&content)?;
+        let base_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+        config.resolve_paths(base_dir)?;
+        Ok(config)
+    }
+
+    // Makes body_file and other paths relative to the config file and checks
+    // that body files exist
+    fn resolve_paths(&mut self, base_dir: &Path) -> crate::Result<()> {
+        let tls_configs = self
+            .server
+            .tls
+            .iter_mut()
+            .chain(self.server.listeners.iter_mut().flatten().filter_map(|l| l.tls.as_mut()));
+        for tls in tls_configs {
+            let paths = [&mut tls.cert_path, &mut tls.key_path, &mut tls.ca_output, &mut tls.client_ca_path];
+            for path in paths.into_iter().flatten() {
+                *path = base_dir.join(&*path);
+            }
+        }
+
+        for listener in self.server.listeners.iter_mut().flatten() {
+            if let Some(unix) = &mut listener.unix {
+                *unix = base_dir.join(&*unix);
+            }
+        }
+
+        for mount in self.static_files.iter_mut().flatten() {
+            mount.root_dir = base_dir.join(&mount.root_dir);
+        }
+
+        let Some(mock) = &mut self.mock else {
+            return Ok(());
+        };
+
+        if let Some(record) = &mut mock.record {
+            record.output = base_dir.join(&record.output);
+        }
+
+        for scenario in &mut mock.scenarios {
+            for route in &mut scenario.routes {
+                let label = format!("{} {}", route.method, route.path);
+                for response in route.responses_mut() {
+                    let Some(body_file) = &response.body_file else {
+                        continue;
+                    };
+
+                    let resolved = base_dir.join(body_file);
+                    // Templated paths can only be checked up to their first placeholder
+                    let static_part: PathBuf = resolved
+                        .components()
+                        .take_while(|c| !matches!(c, Component::Normal(s) if s.to_string_lossy().contains('{')))
+                        .collect();
+
+                    if !static_part.exists() {
+                        return Err(crate::error::Error::Config(format!(
+                            "body_file for {} not found: {}",
+                            label,
+                            static_part.display()
+                        )));
+                    }
+                    response.body_file = Some(resolved);
+                }
+            }
+        }
+
+        Ok(())
     }
 }
\ No newline at end of file
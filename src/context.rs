@@ -0,0 +1,150 @@
+use bytes::{Bytes, BytesMut};
+use http::HeaderMap;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::{Method, Request, Uri};
+use serde_json::Value;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+// Request extension naming the listener a request arrived on
+#[derive(Debug, Clone)]
+pub struct ListenerName(pub Arc<str>);
+
+// Everything the router needs to know about a request, with the body
+// buffered so matchers can inspect it more than once
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub query: Vec<(String, String)>,
+    pub body: Bytes,
+    pub listener: Option<Arc<str>>,
+    json: OnceLock<Option<Value>>,
+}
+
+// The unread rest of a body that ran past the size limit. What was read
+// before it is in `RequestContext::body`
+#[derive(Debug)]
+pub struct Overflow(Incoming);
+
+impl Overflow {
+    pub fn into_inner(self) -> Incoming {
+        self.0
+    }
+}
+
+#[derive(Debug)]
+pub enum BodyError {
+    TooLarge(usize),
+    Read(String),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "Request body exceeds {} bytes", limit),
+            BodyError::Read(e) => write!(f, "Failed to read request body: {}", e),
+        }
+    }
+}
+
+impl RequestContext {
+    pub fn new(method: Method, uri: Uri, headers: HeaderMap, body: Bytes) -> Self {
+        let query = uri
+            .query()
+            .map(|q| {
+                url::form_urlencoded::parse(q.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            method,
+            uri,
+            headers,
+            query,
+            body,
+            listener: None,
+            json: OnceLock::new(),
+        }
+    }
+
+    // Buffers the body up to `max_body_size`. A longer body is left unread
+    // past the frame that crossed the limit and comes back as an `Overflow`,
+    // so it can still be streamed to an upstream
+    pub async fn from_request(
+        req: Request<Incoming>,
+        max_body_size: usize,
+    ) -> Result<(Self, Option<Overflow>), BodyError> {
+        let (parts, mut body) = req.into_parts();
+        let mut buffer = BytesMut::new();
+        let mut overflow = None;
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|e| BodyError::Read(e.to_string()))?;
+            if let Ok(data) = frame.into_data() {
+                buffer.extend_from_slice(&data);
+            }
+            if buffer.len() > max_body_size {
+                overflow = Some(Overflow(body));
+                break;
+            }
+        }
+
+        let listener = parts.extensions.get::<ListenerName>().map(|name| Arc::clone(&name.0));
+        let mut ctx = Self::new(parts.method, parts.uri, parts.headers, buffer.freeze());
+        ctx.listener = listener;
+        Ok((ctx, overflow))
+    }
+
+    pub fn path(&self) -> &str {
+        self.uri.path()
+    }
+
+    // The Host header (or the HTTP/2 authority) without its port
+    pub fn host(&self) -> Option<&str> {
+        let authority = match self.headers.get(http::header::HOST) {
+            Some(value) => value.to_str().ok()?,
+            None => self.uri.authority()?.as_str(),
+        };
+        let host = match authority.rfind(':') {
+            // An IPv6 literal has colons of its own inside the brackets
+            Some(colon) if !authority[colon..].contains(']') => &authority[..colon],
+            _ => authority,
+        };
+        Some(host)
+    }
+
+    pub fn query_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.query
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn header_values<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+    }
+
+    pub fn body_text(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+
+    // The body parsed as JSON, or None if it is empty or not valid JSON
+    pub fn json_body(&self) -> Option<&Value> {
+        self.json
+            .get_or_init(|| serde_json::from_slice(&self.body).ok())
+            .as_ref()
+    }
+
+    pub fn form_body(&self) -> Vec<(String, String)> {
+        url::form_urlencoded::parse(&self.body).into_owned().collect()
+    }
+}
@@ -5,8 +5,11 @@ pub enum Error {
     Hyper(hyper::Error),
     Http(http::Error),
     Io(std::io::Error),
+    Config(String),
     #[cfg(feature = "config")]
     Yaml(serde_yaml::Error),
+    #[cfg(feature = "templates")]
+    Template(handlebars::RenderError),
     Other(String),
 }
 
@@ -16,8 +19,11 @@ impl fmt::Display for Error {
             Error::Hyper(e) => write!(f, "Hyper error: {}", e),
             Error::Http(e) => write!(f, "HTTP error: {}", e),
             Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::Config(s) => write!(f, "Config error: {}", s),
             #[cfg(feature = "config")]
             Error::Yaml(e) => write!(f, "YAML error: {}", e),
+            #[cfg(feature = "templates")]
+            Error::Template(e) => write!(f, "Template error: {}", e),
             Error::Other(s) => write!(f, "Error: {}", s),
         }
     }
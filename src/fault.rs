@@ -0,0 +1,177 @@
+use crate::body::NoxBody;
+use crate::config::Fault;
+use bytes::Bytes;
+use futures::future;
+use futures::stream::{self, StreamExt as _};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use hyper::Response;
+use rand::RngCore;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+const GARBAGE_LEN: usize = 256;
+const FLUSH_GRACE: Duration = Duration::from_millis(20);
+
+// Transport-level faults can't be expressed as an HTTP response, so the
+// router tags the response with the fault and the connection task arms the
+// socket wrapper before hyper writes anything
+#[derive(Debug, Clone, Default)]
+pub struct FaultHandle {
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum FaultState {
+    #[default]
+    Idle,
+    // Fail the next write so the socket closes without a response
+    Close,
+    // Let writes through, then reset the connection instead of closing it
+    Reset,
+    // Replace the response with random bytes, then close
+    Garbage,
+    // A fault has fired; refuse any further I/O
+    Closed,
+}
+
+impl FaultHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn arm<B>(&self, response: &Response<B>) {
+        let state = match response.extensions().get::<Fault>() {
+            Some(Fault::EmptyResponse) => FaultState::Close,
+            Some(Fault::ConnectionReset) => FaultState::Reset,
+            Some(Fault::MalformedResponse) => FaultState::Garbage,
+            _ => return,
+        };
+        *self.state.lock().unwrap() = state;
+    }
+
+    fn get(&self) -> FaultState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set(&self, state: FaultState) {
+        *self.state.lock().unwrap() = state;
+    }
+}
+
+// Sockets that can be closed with a reset rather than an orderly shutdown
+pub trait AbortiveClose {
+    fn abort(&self);
+}
+
+impl AbortiveClose for TcpStream {
+    fn abort(&self) {
+        // A zero linger turns the close into an RST
+        let _ = self.set_zero_linger();
+    }
+}
+
+// Unix sockets have no reset; the close on drop is as abrupt as it gets
+#[cfg(unix)]
+impl AbortiveClose for tokio::net::UnixStream {
+    fn abort(&self) {}
+}
+
+pub struct FaultIo<S: AbortiveClose> {
+    inner: S,
+    faults: FaultHandle,
+}
+
+impl<S: AbortiveClose> FaultIo<S> {
+    pub fn new(inner: S, faults: FaultHandle) -> Self {
+        Self { inner, faults }
+    }
+}
+
+impl<S: AbortiveClose> Drop for FaultIo<S> {
+    fn drop(&mut self) {
+        if self.faults.get() == FaultState::Reset {
+            self.inner.abort();
+        }
+    }
+}
+
+impl<S: AsyncRead + AbortiveClose + Unpin> AsyncRead for FaultIo<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.faults.get() == FaultState::Closed {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + AbortiveClose + Unpin> AsyncWrite for FaultIo<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.faults.get() {
+            FaultState::Idle | FaultState::Reset => Pin::new(&mut self.inner).poll_write(cx, buf),
+            FaultState::Close | FaultState::Closed => {
+                self.faults.set(FaultState::Closed);
+                Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+            }
+            FaultState::Garbage => {
+                let mut garbage = [0u8; GARBAGE_LEN];
+                rand::thread_rng().fill_bytes(&mut garbage);
+                match Pin::new(&mut self.inner).poll_write(cx, &garbage) {
+                    Poll::Ready(Ok(_)) => {
+                        self.faults.set(FaultState::Closed);
+                        // Report the original buffer as written; the connection
+                        // is torn down on the next write or read
+                        Poll::Ready(Ok(buf.len()))
+                    }
+                    other => other,
+                }
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.faults.get() == FaultState::Closed {
+            // Send the FIN right away rather than waiting for hyper to notice
+            return Pin::new(&mut self.inner).poll_shutdown(cx);
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.faults.get() == FaultState::Reset {
+            // Skip the FIN; the RST is sent when the socket is dropped
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// A body that sends the first half of `bytes` and then ends early. Paired with
+// the full Content-Length this leaves the client with a truncated response
+pub fn truncated(bytes: Bytes) -> NoxBody {
+    partial(bytes, false)
+}
+
+// A body that sends the first half of `bytes` and then fails, aborting the
+// connection mid-body
+pub fn interrupted(bytes: Bytes) -> NoxBody {
+    partial(bytes, true)
+}
+
+fn partial(bytes: Bytes, fail: bool) -> NoxBody {
+    let half = bytes.slice(..bytes.len() / 2);
+    let tail = stream::once(async move {
+        // Give hyper a moment to flush what was sent before cutting the body short
+        tokio::time::sleep(FLUSH_GRACE).await;
+        fail.then(|| Err(io::ErrorKind::ConnectionReset.into()))
+    })
+    .filter_map(future::ready);
+
+    let frames = stream::iter([Ok(Frame::data(half))]).chain(tail);
+    BodyExt::boxed(StreamBody::new(frames))
+}
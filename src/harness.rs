@@ -0,0 +1,265 @@
+use crate::config::{BodyMatcher, DelaySpec, Fault, MockResponse, MockRoute, ValueMatcher};
+use crate::journal::{RecordedRequest, RequestQuery};
+use crate::router::MockRouter;
+use crate::shutdown::ShutdownHandle;
+use crate::error::Error;
+use crate::Result;
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+// A server running in the background of a test, from `NoxServer::start` or
+// `NoxServer::start_ephemeral`. Dropping it shuts the server down
+pub struct NoxHandle {
+    addr: SocketAddr,
+    base_url: String,
+    router: Arc<MockRouter>,
+    shutdown: ShutdownHandle,
+    task: Option<JoinHandle<Result<()>>>,
+}
+
+// A route being put together from Rust; `mount` adds it to the running server
+#[must_use = "a stub does nothing until it is mounted"]
+pub struct Stub<'a> {
+    router: &'a MockRouter,
+    route: MockRoute,
+    response: MockResponse,
+}
+
+impl NoxHandle {
+    pub(crate) fn new(
+        addr: SocketAddr,
+        base_url: String,
+        router: Arc<MockRouter>,
+        shutdown: ShutdownHandle,
+        task: JoinHandle<Result<()>>,
+    ) -> Self {
+        Self {
+            addr,
+            base_url,
+            router,
+            shutdown,
+            task: Some(task),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Scheme and address of the default listener, without a trailing slash
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+
+    pub fn router(&self) -> &MockRouter {
+        &self.router
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    // Starts a runtime route answering 200 with an empty body until told otherwise
+    pub fn stub(&self, method: &str, path: &str) -> Stub<'_> {
+        Stub {
+            router: &self.router,
+            route: MockRoute {
+                method: method.to_string(),
+                path: path.to_string(),
+                ..Default::default()
+            },
+            response: MockResponse {
+                status: 200,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn remove_stub(&self, id: &str) -> bool {
+        self.router.remove_route(id)
+    }
+
+    // Back to the state the server started in: runtime stubs and the journal
+    // are cleared, scenario states and sequences rewound
+    pub fn reset(&self) {
+        self.router.clear_runtime_routes();
+        self.router.journal().clear();
+        let _ = self.router.reset_scenario_states(None);
+        self.router.reset_sequences(None);
+    }
+
+    // Every request in the journal, oldest first
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.router.journal().entries()
+    }
+
+    pub fn find(&self, query: &RequestQuery) -> std::result::Result<Vec<RecordedRequest>, String> {
+        Ok(self.router.journal().find(&query.compile()?))
+    }
+
+    // How many journaled requests the route answered
+    pub fn calls(&self, route_id: &str) -> usize {
+        self.requests()
+            .iter()
+            .filter(|r| r.route_id.as_deref() == Some(route_id))
+            .count()
+    }
+
+    #[track_caller]
+    pub fn assert_calls(&self, route_id: &str, expected: usize) {
+        let count = self.calls(route_id);
+        if count != expected {
+            panic!(
+                "expected {} call(s) to route '{}', got {}\nrequests received:\n{}",
+                expected,
+                route_id,
+                count,
+                self.describe_requests()
+            );
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_called(&self, route_id: &str) {
+        if self.calls(route_id) == 0 {
+            panic!(
+                "expected route '{}' to be called\nrequests received:\n{}",
+                route_id,
+                self.describe_requests()
+            );
+        }
+    }
+
+    // Stops accepting, drains and waits for the server to finish
+    pub async fn shutdown(mut self) -> Result<()> {
+        self.shutdown.shutdown();
+        match self.task.take() {
+            Some(task) => task.await.map_err(|e| Error::Other(e.to_string()))?,
+            None => Ok(()),
+        }
+    }
+
+    fn describe_requests(&self) -> String {
+        let requests = self.requests();
+        if requests.is_empty() {
+            return "  (none)".to_string();
+        }
+        requests
+            .iter()
+            .map(|r| {
+                let route = r.route_id.as_deref().unwrap_or("unmatched");
+                format!("  {} {} -> {}", r.request.method, r.request.uri, route)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Drop for NoxHandle {
+    fn drop(&mut self) {
+        // The server drains in the background; `shutdown` waits for it instead
+        self.shutdown.shutdown();
+    }
+}
+
+impl Stub<'_> {
+    pub fn id(mut self, id: &str) -> Self {
+        self.route.id = Some(id.to_string());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.route
+            .headers
+            .get_or_insert_with(Default::default)
+            .insert(name.to_string(), ValueMatcher::Exact(Value::String(value.to_string())));
+        self
+    }
+
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.route
+            .query
+            .get_or_insert_with(Default::default)
+            .insert(name.to_string(), ValueMatcher::Exact(Value::String(value.to_string())));
+        self
+    }
+
+    pub fn body_contains(mut self, text: &str) -> Self {
+        self.route.body.get_or_insert_with(BodyMatcher::default).contains = Some(text.to_string());
+        self
+    }
+
+    pub fn body_json_path(mut self, path: &str, value: impl Into<Value>) -> Self {
+        self.route
+            .body
+            .get_or_insert_with(BodyMatcher::default)
+            .json_path
+            .get_or_insert_with(Default::default)
+            .insert(path.to_string(), value.into());
+        self
+    }
+
+    // Puts the stub in a scenario from config, so it follows the scenario's
+    // enable switch and state
+    pub fn scenario(mut self, name: &str) -> Self {
+        self.route.scenario = Some(name.to_string());
+        self
+    }
+
+    pub fn required_state(mut self, state: &str) -> Self {
+        self.route.required_state = Some(state.to_string());
+        self
+    }
+
+    pub fn new_state(mut self, state: &str) -> Self {
+        self.response.new_state = Some(state.to_string());
+        self
+    }
+
+    pub fn status(mut self, status: u16) -> Self {
+        self.response.status = status;
+        self
+    }
+
+    pub fn response_header(mut self, name: &str, value: &str) -> Self {
+        self.response
+            .headers
+            .get_or_insert_with(Default::default)
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.response.body = body.to_string();
+        self
+    }
+
+    pub fn json(mut self, body: Value) -> Self {
+        self.response.body_json = Some(body);
+        self
+    }
+
+    // Milliseconds before the response is sent
+    pub fn delay(mut self, delay: u64) -> Self {
+        self.response.delay = Some(DelaySpec::Fixed(delay));
+        self
+    }
+
+    pub fn fault(mut self, fault: Fault) -> Self {
+        self.response.fault = Some(fault);
+        self
+    }
+
+    // Adds the route to the running server and returns its id, for
+    // `assert_calls` and `remove_stub`
+    pub fn mount(mut self) -> std::result::Result<String, String> {
+        self.route.response = Some(self.response);
+        self.router.add_runtime_route(&self.route).map(|info| info.id)
+    }
+}
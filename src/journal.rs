@@ -0,0 +1,240 @@
+use crate::config::{BodyMatcher, ValueMatcher};
+use crate::context::RequestContext;
+use crate::matcher::RequestMatcher;
+use crate::pattern::PathPattern;
+use hyper::Method;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_JOURNAL_SIZE: usize = 1000;
+pub const DEFAULT_JOURNAL_BODY_LIMIT: usize = 64 * 1024;
+
+// A bounded, in-memory history of the requests the mock has served. Once full
+// the oldest entries are dropped, and each entry keeps at most `body_limit`
+// bytes of its body
+#[derive(Debug)]
+pub struct Journal {
+    capacity: usize,
+    body_limit: usize,
+    inner: Mutex<JournalInner>,
+}
+
+#[derive(Debug, Default)]
+struct JournalInner {
+    entries: VecDeque<RecordedRequest>,
+    next_id: u64,
+}
+
+// Criteria for picking requests out of the journal. Everything is optional and
+// the header, query and body rules work the same as on a route
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RequestQuery {
+    pub route: Option<String>,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub listener: Option<String>,
+    pub headers: Option<HashMap<String, ValueMatcher>>,
+    pub query: Option<HashMap<String, ValueMatcher>>,
+    pub body: Option<BodyMatcher>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestFilter {
+    route: Option<String>,
+    method: Option<Method>,
+    path: Option<PathPattern>,
+    listener: Option<String>,
+    request: RequestMatcher,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub id: u64,
+    pub timestamp_ms: u64,
+    pub route_id: Option<String>,
+    pub request: RequestContext,
+    pub body_truncated: bool,
+}
+
+impl Journal {
+    pub fn new(capacity: usize, body_limit: usize) -> Self {
+        Self {
+            capacity,
+            body_limit,
+            inner: Mutex::new(JournalInner::default()),
+        }
+    }
+
+    pub fn record(&self, request: &RequestContext, route_id: Option<&str>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        // A long body is copied rather than sliced, so the entry doesn't keep
+        // the whole buffer alive
+        let body_truncated = request.body.len() > self.body_limit;
+        let request = if body_truncated {
+            let body = Bytes::copy_from_slice(&request.body[..self.body_limit]);
+            let mut truncated =
+                RequestContext::new(request.method.clone(), request.uri.clone(), request.headers.clone(), body);
+            truncated.listener = request.listener.clone();
+            truncated
+        } else {
+            request.clone()
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        let entry = RecordedRequest {
+            id: inner.next_id,
+            timestamp_ms,
+            route_id: route_id.map(str::to_string),
+            request,
+            body_truncated,
+        };
+        if inner.entries.len() == self.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(entry);
+    }
+
+    // Oldest first
+    pub fn entries(&self) -> Vec<RecordedRequest> {
+        self.inner.lock().unwrap().entries.iter().cloned().collect()
+    }
+
+    pub fn find(&self, filter: &RequestFilter) -> Vec<RecordedRequest> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|entry| filter.matches(entry))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new(DEFAULT_JOURNAL_SIZE, DEFAULT_JOURNAL_BODY_LIMIT)
+    }
+}
+
+impl RequestQuery {
+    pub fn compile(&self) -> Result<RequestFilter, String> {
+        let method = self
+            .method
+            .as_deref()
+            .map(|m| m.to_uppercase().parse::<Method>())
+            .transpose()
+            .map_err(|_| format!("invalid method '{}'", self.method.as_deref().unwrap_or_default()))?;
+
+        Ok(RequestFilter {
+            route: self.route.clone(),
+            method,
+            path: self.path.as_deref().map(PathPattern::parse).transpose()?,
+            listener: self.listener.clone(),
+            request: RequestMatcher::from_parts(self.headers.as_ref(), self.query.as_ref(), self.body.as_ref())?,
+        })
+    }
+}
+
+impl RequestFilter {
+    pub fn matches(&self, entry: &RecordedRequest) -> bool {
+        let ctx = &entry.request;
+        self.route.as_ref().is_none_or(|route| entry.route_id.as_ref() == Some(route))
+            && self.method.as_ref().is_none_or(|method| ctx.method == *method)
+            && self.path.as_ref().is_none_or(|path| path.matches(ctx.path()).is_some())
+            && self.listener.as_deref().is_none_or(|listener| ctx.listener.as_deref() == Some(listener))
+            && self.request.matches(ctx)
+    }
+}
+
+impl RecordedRequest {
+    pub fn to_json(&self) -> Value {
+        let ctx = &self.request;
+
+        let mut query = Map::new();
+        for (key, value) in &ctx.query {
+            query
+                .entry(key.clone())
+                .or_insert_with(|| Value::String(value.clone()));
+        }
+
+        let mut headers = Map::new();
+        for name in ctx.headers.keys() {
+            let values: Vec<&str> = ctx.header_values(name.as_str()).collect();
+            headers.insert(name.as_str().to_string(), Value::String(values.join(", ")));
+        }
+
+        json!({
+            "id": self.id,
+            "timestamp_ms": self.timestamp_ms,
+            "route_id": self.route_id,
+            "method": ctx.method.as_str(),
+            "path": ctx.path(),
+            "listener": ctx.listener.as_deref(),
+            "query": query,
+            "headers": headers,
+            "body": ctx.body_text(),
+            "body_truncated": self.body_truncated,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderMap;
+
+    fn request(body: &str) -> RequestContext {
+        RequestContext::new(Method::POST, "/upload".parse().unwrap(), HeaderMap::new(), Bytes::from(body.to_string()))
+    }
+
+    #[test]
+    fn test_long_bodies_are_truncated() {
+        let journal = Journal::new(10, 4);
+        journal.record(&request("abcdefgh"), None);
+        journal.record(&request("abc"), None);
+
+        let entries = journal.entries();
+        assert_eq!(&entries[0].request.body[..], b"abcd");
+        assert!(entries[0].body_truncated);
+        assert_eq!(entries[0].to_json()["body_truncated"], true);
+        assert_eq!(&entries[1].request.body[..], b"abc");
+        assert!(!entries[1].body_truncated);
+    }
+
+    #[test]
+    fn test_oldest_entries_are_dropped() {
+        let journal = Journal::new(2, 64);
+        for body in ["1", "2", "3"] {
+            journal.record(&request(body), None);
+        }
+
+        let bodies: Vec<_> = journal.entries().iter().map(|e| e.request.body_text().into_owned()).collect();
+        assert_eq!(bodies, ["2", "3"]);
+        assert_eq!(journal.entries()[1].id, 3);
+    }
+
+    #[test]
+    fn test_size_zero_disables_journal() {
+        let journal = Journal::new(0, 64);
+        journal.record(&request("x"), None);
+        assert!(journal.entries().is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use serde_json::Value;
+use std::fmt;
+
+// A small JSONPath subset, enough for request matching and response rewrites:
+//   $.user.role   $['user']['role']   $.items[0].id   $.items[*].id   $.*
+#[derive(Debug, Clone)]
+pub struct JsonPath {
+    raw: String,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let rest = path
+            .strip_prefix('$')
+            .ok_or_else(|| format!("JSONPath '{}' must start with '$'", path))?;
+        let chars: Vec<char> = rest.chars().collect();
+        let mut steps = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '.' => {
+                    let start = i + 1;
+                    let mut end = start;
+                    while end < chars.len() && chars[end] != '.' && chars[end] != '[' {
+                        end += 1;
+                    }
+                    let key: String = chars[start..end].iter().collect();
+                    match key.as_str() {
+                        "" => return Err(format!("empty key in JSONPath '{}'", path)),
+                        "*" => steps.push(Step::Wildcard),
+                        _ => steps.push(Step::Key(key)),
+                    }
+                    i = end;
+                }
+                '[' => {
+                    let close = chars[i..]
+                        .iter()
+                        .position(|&c| c == ']')
+                        .map(|offset| i + offset)
+                        .ok_or_else(|| format!("unclosed '[' in JSONPath '{}'", path))?;
+                    let inner: String = chars[i + 1..close].iter().collect();
+                    let inner = inner.trim();
+                    if inner == "*" {
+                        steps.push(Step::Wildcard);
+                    } else if let Some(quoted) = strip_quotes(inner) {
+                        steps.push(Step::Key(quoted.to_string()));
+                    } else {
+                        let index = inner
+                            .parse::<usize>()
+                            .map_err(|_| format!("invalid index '{}' in JSONPath '{}'", inner, path))?;
+                        steps.push(Step::Index(index));
+                    }
+                    i = close + 1;
+                }
+                other => {
+                    return Err(format!("unexpected '{}' in JSONPath '{}'", other, path));
+                }
+            }
+        }
+
+        Ok(Self {
+            raw: path.to_string(),
+            steps,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    // Returns every node selected by the path
+    pub fn query<'a>(&self, root: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![root];
+        for step in &self.steps {
+            let mut next = Vec::new();
+            for value in current {
+                match (step, value) {
+                    (Step::Key(key), Value::Object(map)) => next.extend(map.get(key)),
+                    (Step::Index(index), Value::Array(items)) => next.extend(items.get(*index)),
+                    (Step::Wildcard, Value::Object(map)) => next.extend(map.values()),
+                    (Step::Wildcard, Value::Array(items)) => next.extend(items.iter()),
+                    _ => {}
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    // Replaces every selected node with `value`. A missing key at the end of
+    // the path is added; anything else missing is left alone. Returns how many
+    // nodes were written
+    pub fn set(&self, root: &mut Value, value: &Value) -> usize {
+        let Some((last, parents)) = self.steps.split_last() else {
+            *root = value.clone();
+            return 1;
+        };
+
+        let mut count = 0;
+        for parent in select_mut(root, parents) {
+            match (last, parent) {
+                (Step::Key(key), Value::Object(map)) => {
+                    map.insert(key.clone(), value.clone());
+                    count += 1;
+                }
+                (Step::Index(index), Value::Array(items)) => {
+                    if let Some(item) = items.get_mut(*index) {
+                        *item = value.clone();
+                        count += 1;
+                    }
+                }
+                (Step::Wildcard, Value::Object(map)) => {
+                    count += map.len();
+                    map.values_mut().for_each(|v| *v = value.clone());
+                }
+                (Step::Wildcard, Value::Array(items)) => {
+                    count += items.len();
+                    items.iter_mut().for_each(|v| *v = value.clone());
+                }
+                _ => {}
+            }
+        }
+        count
+    }
+
+    // Removes every selected node from its parent object or array. Returns
+    // how many were removed
+    pub fn remove(&self, root: &mut Value) -> usize {
+        let Some((last, parents)) = self.steps.split_last() else {
+            return 0;
+        };
+
+        let mut count = 0;
+        for parent in select_mut(root, parents) {
+            match (last, parent) {
+                (Step::Key(key), Value::Object(map)) => count += usize::from(map.remove(key).is_some()),
+                (Step::Index(index), Value::Array(items)) if *index < items.len() => {
+                    items.remove(*index);
+                    count += 1;
+                }
+                (Step::Wildcard, Value::Object(map)) => {
+                    count += map.len();
+                    map.clear();
+                }
+                (Step::Wildcard, Value::Array(items)) => {
+                    count += items.len();
+                    items.clear();
+                }
+                _ => {}
+            }
+        }
+        count
+    }
+}
+
+fn select_mut<'a>(root: &'a mut Value, steps: &[Step]) -> Vec<&'a mut Value> {
+    let mut current = vec![root];
+    for step in steps {
+        let mut next = Vec::new();
+        for value in current {
+            match (step, value) {
+                (Step::Key(key), Value::Object(map)) => next.extend(map.get_mut(key)),
+                (Step::Index(index), Value::Array(items)) => next.extend(items.get_mut(*index)),
+                (Step::Wildcard, Value::Object(map)) => next.extend(map.values_mut()),
+                (Step::Wildcard, Value::Array(items)) => next.extend(items.iter_mut()),
+                _ => {}
+            }
+        }
+        current = next;
+    }
+    current
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+fn strip_quotes(s: &str) -> Option<&str> {
+    s.strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .or_else(|| s.strip_prefix('"').and_then(|s| s.strip_suffix('"')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn query(path: &str, root: &Value) -> Vec<Value> {
+        JsonPath::parse(path).unwrap().query(root).into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_parse_steps() {
+        let steps = |path: &str| JsonPath::parse(path).unwrap().steps;
+
+        assert_eq!(steps("$"), vec![]);
+        assert_eq!(steps("$.user.role"), vec![Step::Key("user".into()), Step::Key("role".into())]);
+        assert_eq!(steps("$['user'][\"role\"]"), vec![Step::Key("user".into()), Step::Key("role".into())]);
+        assert_eq!(steps("$.items[0].id"), vec![Step::Key("items".into()), Step::Index(0), Step::Key("id".into())]);
+        assert_eq!(steps("$.items[*]"), vec![Step::Key("items".into()), Step::Wildcard]);
+        assert_eq!(steps("$.*"), vec![Step::Wildcard]);
+        assert_eq!(steps("$[ 2 ]"), vec![Step::Index(2)]);
+    }
+
+    #[test]
+    fn test_quoted_keys_keep_special_characters() {
+        let root = json!({"a.b": 1, "x y": 2, "*": 3, "0": 4});
+        assert_eq!(query("$['a.b']", &root), vec![json!(1)]);
+        assert_eq!(query("$[\"x y\"]", &root), vec![json!(2)]);
+        assert_eq!(query("$['*']", &root), vec![json!(3)]);
+        assert_eq!(query("$['0']", &root), vec![json!(4)]);
+        // An unquoted number is an index, which an object doesn't have
+        assert!(query("$[0]", &root).is_empty());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for path in ["user.role", "$.", "$..role", "$.a.", "$[0", "$[x]", "$[-1]", "$[]", "$a"] {
+            assert!(JsonPath::parse(path).is_err(), "'{}' should not parse", path);
+        }
+    }
+
+    #[test]
+    fn test_query_indexes_and_wildcards() {
+        let root = json!({"items": [{"id": 1}, {"id": 2}, {"name": "x"}], "total": 2});
+
+        assert_eq!(query("$.items[1].id", &root), vec![json!(2)]);
+        assert_eq!(query("$.items[*].id", &root), vec![json!(1), json!(2)]);
+        assert!(query("$.items[9].id", &root).is_empty());
+        assert!(query("$.total.id", &root).is_empty());
+        assert!(query("$.missing", &root).is_empty());
+        assert_eq!(query("$", &root), vec![root.clone()]);
+    }
+
+    #[test]
+    fn test_set_and_remove() {
+        let mut root = json!({"user": {"role": "admin"}, "items": [{"id": 1}, {"id": 2}]});
+
+        assert_eq!(JsonPath::parse("$.user.name").unwrap().set(&mut root, &json!("ada")), 1);
+        assert_eq!(JsonPath::parse("$.items[*].id").unwrap().set(&mut root, &json!(0)), 2);
+        // Only the last step may be missing
+        assert_eq!(JsonPath::parse("$.nope.name").unwrap().set(&mut root, &json!(1)), 0);
+        assert_eq!(root, json!({"user": {"role": "admin", "name": "ada"}, "items": [{"id": 0}, {"id": 0}]}));
+
+        assert_eq!(JsonPath::parse("$.user.role").unwrap().remove(&mut root), 1);
+        assert_eq!(JsonPath::parse("$.items[5]").unwrap().remove(&mut root), 0);
+        assert_eq!(JsonPath::parse("$.items[0]").unwrap().remove(&mut root), 1);
+        assert_eq!(root, json!({"user": {"name": "ada"}, "items": [{"id": 0}]}));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::config::{DelaySpec, LatencyDistribution};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+// z-score of the 99th percentile of a standard normal distribution
+const Z_P99: f64 = 2.326_348;
+
+// Default cap for the p50/p99 distributions, as a multiple of p99
+const DEFAULT_MAX_P99_MULTIPLE: u64 = 10;
+
+impl DelaySpec {
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        let millis = match self {
+            DelaySpec::Fixed(ms) => *ms as f64,
+            DelaySpec::Range { min, max } => uniform(rng, *min, *max),
+            DelaySpec::Distribution(distribution) => distribution.sample(rng),
+        };
+        // Out of range samples (only possible with an enormous `max`) are
+        // clamped rather than allowed to panic
+        Duration::try_from_secs_f64(millis.max(0.0) / 1000.0).unwrap_or(Duration::MAX)
+    }
+}
+
+impl LatencyDistribution {
+    // Samples a latency in milliseconds
+    fn sample(&self, rng: &mut impl Rng) -> f64 {
+        match *self {
+            LatencyDistribution::Uniform { min, max } => uniform(rng, min, max),
+            LatencyDistribution::Normal { p50, p99, max } => {
+                let sigma = (p99.saturating_sub(p50)) as f64 / Z_P99;
+                let millis = p50 as f64 + sigma * standard_normal(rng);
+                millis.min(cap(p99, max))
+            }
+            LatencyDistribution::LogNormal { p50, p99, max } => {
+                let mu = (p50.max(1) as f64).ln();
+                let sigma = ((p99.max(p50).max(1) as f64).ln() - mu) / Z_P99;
+                (mu + sigma * standard_normal(rng)).exp().min(cap(p99, max))
+            }
+            LatencyDistribution::Pareto { p50, p99, max } => {
+                // Solve scale and shape so the median and p99 land where configured
+                let p50 = p50.max(1) as f64;
+                let ratio = (p99 as f64 / p50).max(1.0 + f64::EPSILON);
+                let shape = 50f64.ln() / ratio.ln();
+                let scale = p50 / 2f64.powf(1.0 / shape);
+                let u: f64 = 1.0 - rng.gen::<f64>();
+                (scale * u.powf(-1.0 / shape)).min(cap(p99, max))
+            }
+        }
+    }
+}
+
+// The tails are unbounded; a heavy one could otherwise sleep for years
+fn cap(p99: u64, max: Option<u64>) -> f64 {
+    max.unwrap_or_else(|| p99.saturating_mul(DEFAULT_MAX_P99_MULTIPLE)) as f64
+}
+
+fn uniform(rng: &mut impl Rng, min: u64, max: u64) -> f64 {
+    if min >= max {
+        min as f64
+    } else {
+        rng.gen_range(min..=max) as f64
+    }
+}
+
+// Box-Muller transform
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn max_sample(spec: &DelaySpec) -> Duration {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..10_000).map(|_| spec.sample(&mut rng)).max().unwrap()
+    }
+
+    #[test]
+    fn test_heavy_tails_are_capped() {
+        let pareto = |max| DelaySpec::Distribution(LatencyDistribution::Pareto { p50: 10, p99: 10_000, max });
+        assert!(max_sample(&pareto(None)) <= Duration::from_secs(100));
+        assert!(max_sample(&pareto(Some(2_000))) <= Duration::from_secs(2));
+
+        let lognormal = DelaySpec::Distribution(LatencyDistribution::LogNormal { p50: 1, p99: 100_000, max: None });
+        assert!(max_sample(&lognormal) <= Duration::from_secs(1_000));
+    }
+
+    #[test]
+    fn test_extreme_values_do_not_panic() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let pareto = DelaySpec::Distribution(LatencyDistribution::Pareto { p50: 1, p99: u64::MAX, max: Some(u64::MAX) });
+        for _ in 0..1_000 {
+            pareto.sample(&mut rng);
+        }
+        assert!(DelaySpec::Fixed(u64::MAX).sample(&mut rng) > Duration::from_secs(1 << 50));
+    }
+
+    #[test]
+    fn test_fixed_and_range() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(DelaySpec::Fixed(250).sample(&mut rng), Duration::from_millis(250));
+        for _ in 0..100 {
+            let sample = DelaySpec::Range { min: 100, max: 300 }.sample(&mut rng);
+            assert!((Duration::from_millis(100)..=Duration::from_millis(300)).contains(&sample));
+        }
+    }
+}
@@ -1,8 +1,41 @@
 pub mod server;
 pub mod error;
+pub mod admin;
 pub mod router;
+pub mod pattern;
+pub mod context;
+pub mod matcher;
+pub mod journal;
+pub mod jsonpath;
+pub mod body;
+pub mod latency;
+pub mod fault;
+pub mod static_files;
+pub mod shutdown;
+pub mod harness;
 
 #[cfg(feature = "config")]
 pub mod config;
 
+#[cfg(feature = "templates")]
+pub mod template;
+
+#[cfg(feature = "proxy")]
+pub mod upstream;
+
+#[cfg(feature = "proxy")]
+pub mod record;
+
+#[cfg(feature = "proxy")]
+pub mod proxy;
+
+#[cfg(feature = "proxy")]
+pub mod circuit;
+
+#[cfg(feature = "proxy")]
+pub mod transform;
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
 pub use error::Result;
\ No newline at end of file
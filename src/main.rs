@@ -4,7 +4,7 @@ use nox::server::NoxServer;
 use nox::config::NoxConfig;
 
 #[cfg(feature = "config")]
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 
 #[tokio::main]
 async fn main() -> nox::Result<()> {
@@ -21,25 +21,45 @@ async fn main() -> nox::Result<()> {
                     .help("Configuration file path")
                     .required(false),
             )
+            .arg(
+                Arg::new("tls-self-signed")
+                    .long("tls-self-signed")
+                    .help("Serve HTTPS with a throwaway CA and certificate generated at startup")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("tls-ca-out")
+                    .long("tls-ca-out")
+                    .value_name("FILE")
+                    .help("Where to write the generated CA certificate (default: nox-ca.pem)")
+                    .required(false),
+            )
             .get_matches();
 
-        if let Some(config_path) = matches.get_one::<String>("config") {
+        let mut config = if let Some(config_path) = matches.get_one::<String>("config") {
             println!("Loading config from: {}", config_path);
-            let config = NoxConfig::load_from_file(config_path)?;
-            let server = NoxServer::from_config(&config);
-            server.run().await
+            NoxConfig::load_from_file(config_path)?
         } else {
             println!("No config file specified, using default settings");
-            let config = NoxConfig::default();
-            let server = NoxServer::from_config(&config);
-            server.run().await
+            NoxConfig::default()
+        };
+
+        if matches.get_flag("tls-self-signed") {
+            let tls = config.server.tls.get_or_insert_with(Default::default);
+            tls.self_signed = Some(true);
+            if let Some(ca_out) = matches.get_one::<String>("tls-ca-out") {
+                tls.ca_output = Some(ca_out.into());
+            }
         }
+
+        let server = NoxServer::from_config(&config).with_signal_shutdown();
+        server.run().await
     }
 
     #[cfg(not(feature = "config"))]
     {
         let addr: SocketAddr = "127.0.0.1:3000".parse().unwrap();
-        let server = NoxServer::new(addr);
+        let server = NoxServer::new(addr).with_signal_shutdown();
         server.run().await
     }
 }
\ No newline at end of file
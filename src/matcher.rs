@@ -0,0 +1,350 @@
+use crate::config::{BodyMatcher, MockRoute, ValueMatcher};
+use crate::context::RequestContext;
+use crate::jsonpath::JsonPath;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+// Header, query and body conditions of a route, compiled once at load time
+#[derive(Debug, Clone, Default)]
+pub struct RequestMatcher {
+    headers: Vec<(String, ValueCondition)>,
+    query: Vec<(String, ValueCondition)>,
+    body: Option<BodyCondition>,
+}
+
+#[derive(Debug, Clone)]
+struct ValueCondition {
+    checks: Vec<Check>,
+    present: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+enum Check {
+    Equals(String),
+    Contains(String),
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone, Default)]
+struct BodyCondition {
+    text: Vec<Check>,
+    json_path: Vec<(JsonPath, Value)>,
+    json_partial: Option<Value>,
+    form: Vec<(String, ValueCondition)>,
+}
+
+// The first condition a request failed, for explaining why a route didn't match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Header(String),
+    Query(String),
+    Body(String),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Header(name) => write!(f, "header '{}' does not match", name),
+            Mismatch::Query(name) => write!(f, "query parameter '{}' does not match", name),
+            Mismatch::Body(detail) => write!(f, "body does not match ({})", detail),
+        }
+    }
+}
+
+impl RequestMatcher {
+    pub fn compile(route: &MockRoute) -> Result<Self, String> {
+        Self::from_parts(route.headers.as_ref(), route.query.as_ref(), route.body.as_ref())
+    }
+
+    pub fn from_parts(
+        headers: Option<&HashMap<String, ValueMatcher>>,
+        query: Option<&HashMap<String, ValueMatcher>>,
+        body: Option<&BodyMatcher>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            headers: compile_values(headers)?,
+            query: compile_values(query)?,
+            body: body.map(BodyCondition::compile).transpose()?,
+        })
+    }
+
+    pub fn matches(&self, ctx: &RequestContext) -> bool {
+        self.mismatch(ctx).is_none()
+    }
+
+    pub fn mismatch(&self, ctx: &RequestContext) -> Option<Mismatch> {
+        if let Some((name, _)) = self
+            .headers
+            .iter()
+            .find(|(name, condition)| !condition.matches(ctx.header_values(name)))
+        {
+            return Some(Mismatch::Header(name.clone()));
+        }
+        if let Some((name, _)) = self
+            .query
+            .iter()
+            .find(|(name, condition)| !condition.matches(ctx.query_values(name)))
+        {
+            return Some(Mismatch::Query(name.clone()));
+        }
+        self.body.as_ref()?.mismatch(ctx).map(Mismatch::Body)
+    }
+}
+
+fn compile_values(values: Option<&HashMap<String, ValueMatcher>>) -> Result<Vec<(String, ValueCondition)>, String> {
+    let Some(values) = values else {
+        return Ok(Vec::new());
+    };
+
+    let mut compiled = values
+        .iter()
+        .map(|(name, matcher)| Ok((name.clone(), ValueCondition::compile(matcher)?)))
+        .collect::<Result<Vec<_>, String>>()?;
+    compiled.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(compiled)
+}
+
+impl ValueCondition {
+    fn compile(matcher: &ValueMatcher) -> Result<Self, String> {
+        match matcher {
+            ValueMatcher::Exact(value) => Ok(Self {
+                checks: vec![Check::Equals(scalar_to_string(value)?)],
+                present: None,
+            }),
+            ValueMatcher::Rule(rule) => {
+                let mut checks = Vec::new();
+                if let Some(equals) = &rule.equals {
+                    checks.push(Check::Equals(equals.clone()));
+                }
+                if let Some(contains) = &rule.contains {
+                    checks.push(Check::Contains(contains.clone()));
+                }
+                if let Some(pattern) = &rule.regex {
+                    checks.push(Check::regex(pattern)?);
+                }
+                Ok(Self {
+                    checks,
+                    present: rule.present,
+                })
+            }
+        }
+    }
+
+    fn matches<'a>(&self, mut values: impl Iterator<Item = &'a str>) -> bool {
+        if self.present == Some(false) {
+            return values.next().is_none();
+        }
+        values.any(|value| self.checks.iter().all(|check| check.matches(value)))
+    }
+}
+
+impl Check {
+    fn regex(pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern)
+            .map(Check::Regex)
+            .map_err(|e| format!("invalid regex '{}': {}", pattern, e))
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Check::Equals(expected) => value == expected,
+            Check::Contains(needle) => value.contains(needle.as_str()),
+            Check::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl BodyCondition {
+    fn compile(matcher: &BodyMatcher) -> Result<Self, String> {
+        let mut condition = BodyCondition::default();
+
+        if let Some(equals) = &matcher.equals {
+            condition.text.push(Check::Equals(equals.clone()));
+        }
+        if let Some(contains) = &matcher.contains {
+            condition.text.push(Check::Contains(contains.clone()));
+        }
+        if let Some(pattern) = &matcher.regex {
+            condition.text.push(Check::regex(pattern)?);
+        }
+        if let Some(paths) = &matcher.json_path {
+            for (path, expected) in paths {
+                condition.json_path.push((JsonPath::parse(path)?, expected.clone()));
+            }
+            condition.json_path.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        }
+        condition.json_partial = matcher.json_partial.clone();
+        condition.form = compile_values(matcher.form.as_ref())?;
+
+        Ok(condition)
+    }
+
+    // Describes the first body rule the request fails, if any
+    fn mismatch(&self, ctx: &RequestContext) -> Option<String> {
+        if !self.text.is_empty() {
+            let text = ctx.body_text();
+            if !self.text.iter().all(|check| check.matches(&text)) {
+                return Some("text".to_string());
+            }
+        }
+
+        if !self.json_path.is_empty() || self.json_partial.is_some() {
+            let Some(json) = ctx.json_body() else {
+                return Some("not JSON".to_string());
+            };
+            if let Some((path, _)) = self
+                .json_path
+                .iter()
+                .find(|(path, expected)| !path.query(json).into_iter().any(|node| node == expected))
+            {
+                return Some(format!("json_path {}", path.as_str()));
+            }
+            if let Some(partial) = &self.json_partial {
+                if !json_contains(json, partial) {
+                    return Some("json_partial".to_string());
+                }
+            }
+        }
+
+        if !self.form.is_empty() {
+            let form = ctx.form_body();
+            let failed = self.form.iter().find(|(name, condition)| {
+                !condition.matches(
+                    form.iter()
+                        .filter(|(key, _)| key == name)
+                        .map(|(_, value)| value.as_str()),
+                )
+            });
+            if let Some((name, _)) = failed {
+                return Some(format!("form field '{}'", name));
+            }
+        }
+
+        None
+    }
+}
+
+// True if every field of `expected` is present in `actual`; array elements in
+// `expected` must each match some element of `actual`
+fn json_contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected.iter().all(|(key, value)| {
+            actual
+                .get(key)
+                .is_some_and(|actual| json_contains(actual, value))
+        }),
+        (Value::Array(actual), Value::Array(expected)) => expected
+            .iter()
+            .all(|value| actual.iter().any(|actual| json_contains(actual, value))),
+        _ => actual == expected,
+    }
+}
+
+fn scalar_to_string(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        other => Err(format!("expected a string, number or boolean, got {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{HeaderMap, HeaderValue, Method};
+    use serde_json::json;
+
+    fn request(uri: &str, headers: &[(&'static str, &'static str)], body: &str) -> RequestContext {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        RequestContext::new(Method::POST, uri.parse().unwrap(), map, Bytes::from(body.to_string()))
+    }
+
+    fn values(rules: Value) -> HashMap<String, ValueMatcher> {
+        serde_json::from_value(rules).unwrap()
+    }
+
+    #[test]
+    fn test_json_contains() {
+        let actual = json!({"user": {"id": 7, "tags": ["a", "b", "c"]}, "total": 3});
+
+        assert!(json_contains(&actual, &json!({})));
+        assert!(json_contains(&actual, &json!({"user": {"id": 7}})));
+        assert!(json_contains(&actual, &json!({"user": {"tags": ["c", "a"]}})));
+        assert!(!json_contains(&actual, &json!({"user": {"tags": ["d"]}})));
+        assert!(!json_contains(&actual, &json!({"user": {"id": "7"}})));
+        assert!(!json_contains(&actual, &json!({"missing": null})));
+        assert!(!json_contains(&actual, &json!({"total": [3]})));
+
+        // Array elements match partially too
+        let actual = json!([{"id": 1, "name": "x"}, {"id": 2}]);
+        assert!(json_contains(&actual, &json!([{"id": 2}, {"name": "x"}])));
+        assert!(!json_contains(&actual, &json!([{"id": 3}])));
+    }
+
+    #[test]
+    fn test_present_false_requires_absence() {
+        let headers = values(json!({"authorization": {"present": false}}));
+        let matcher = RequestMatcher::from_parts(Some(&headers), None, None).unwrap();
+
+        assert!(matcher.matches(&request("/", &[], "")));
+        assert_eq!(
+            matcher.mismatch(&request("/", &[("authorization", "Bearer x")], "")),
+            Some(Mismatch::Header("authorization".to_string()))
+        );
+        // An empty value still counts as present
+        assert!(!matcher.matches(&request("/", &[("authorization", "")], "")));
+
+        let query = values(json!({"debug": {"present": false}}));
+        let matcher = RequestMatcher::from_parts(None, Some(&query), None).unwrap();
+        assert!(matcher.matches(&request("/?other=1", &[], "")));
+        assert!(!matcher.matches(&request("/?debug", &[], "")));
+    }
+
+    #[test]
+    fn test_present_true_and_value_rules() {
+        let headers = values(json!({
+            "x-id": {"present": true},
+            "x-env": {"regex": "^(dev|test)$"},
+            "x-plain": 5,
+        }));
+        let matcher = RequestMatcher::from_parts(Some(&headers), None, None).unwrap();
+
+        assert!(matcher.matches(&request("/", &[("x-id", ""), ("x-env", "test"), ("x-plain", "5")], "")));
+        assert!(!matcher.matches(&request("/", &[("x-env", "test"), ("x-plain", "5")], "")));
+        // Any one of the repeated values may satisfy the rule
+        assert!(matcher.matches(&request("/", &[("x-id", "1"), ("x-env", "prod"), ("x-env", "dev"), ("x-plain", "5")], "")));
+
+        let invalid = values(json!({"x-env": {"regex": "("}}));
+        assert!(RequestMatcher::from_parts(Some(&invalid), None, None).is_err());
+    }
+
+    #[test]
+    fn test_body_json_rules() {
+        let body: BodyMatcher = serde_json::from_value(json!({
+            "json_path": {"$.items[*].sku": "A1", "$['user']['role']": "admin"},
+            "json_partial": {"user": {"id": 7}},
+        }))
+        .unwrap();
+        let matcher = RequestMatcher::from_parts(None, None, Some(&body)).unwrap();
+
+        let good = r#"{"user": {"id": 7, "role": "admin"}, "items": [{"sku": "B2"}, {"sku": "A1"}]}"#;
+        assert!(matcher.matches(&request("/", &[], good)));
+
+        let wrong_role = r#"{"user": {"id": 7, "role": "guest"}, "items": [{"sku": "A1"}]}"#;
+        assert_eq!(
+            matcher.mismatch(&request("/", &[], wrong_role)),
+            Some(Mismatch::Body("json_path $['user']['role']".to_string()))
+        );
+        assert_eq!(
+            matcher.mismatch(&request("/", &[], "not json")),
+            Some(Mismatch::Body("not JSON".to_string()))
+        );
+    }
+}
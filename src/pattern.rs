@@ -0,0 +1,260 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+// Path patterns used by mock routes:
+//   /users/{id}       named parameter, matches exactly one segment
+//   /users/{id?}      optional parameter, matches one segment or none
+//   /files/*rest      wildcard, matches the remaining segments (must be last)
+#[derive(Debug, Clone)]
+pub struct PathPattern {
+    raw: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Optional(String),
+    Wildcard(String),
+}
+
+impl Segment {
+    // Lower rank wins: static segments beat params beat wildcards
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Static(_) => 0,
+            Segment::Param(_) => 1,
+            Segment::Optional(_) => 2,
+            Segment::Wildcard(_) => 3,
+        }
+    }
+}
+
+pub type PathParams = HashMap<String, String>;
+
+impl PathPattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        if !pattern.starts_with('/') {
+            return Err(format!("path pattern '{}' must start with '/'", pattern));
+        }
+
+        let mut segments = Vec::new();
+        let parts = split_path(pattern);
+
+        for (index, part) in parts.iter().enumerate() {
+            let segment = if let Some(inner) = part.strip_prefix('{') {
+                let name = inner
+                    .strip_suffix('}')
+                    .ok_or_else(|| format!("unclosed '{{' in path pattern '{}'", pattern))?;
+                match name.strip_suffix('?') {
+                    Some(name) => Segment::Optional(validate_name(name, pattern)?),
+                    None => Segment::Param(validate_name(name, pattern)?),
+                }
+            } else if let Some(name) = part.strip_prefix('*') {
+                if index != parts.len() - 1 {
+                    return Err(format!(
+                        "wildcard must be the last segment in path pattern '{}'",
+                        pattern
+                    ));
+                }
+                let name = if name.is_empty() { "wildcard" } else { name };
+                Segment::Wildcard(validate_name(name, pattern)?)
+            } else {
+                if part.contains('{') || part.contains('}') {
+                    return Err(format!(
+                        "parameters must span a whole segment in path pattern '{}'",
+                        pattern
+                    ));
+                }
+                Segment::Static(part.to_string())
+            };
+            segments.push(segment);
+        }
+
+        Ok(Self {
+            raw: pattern.to_string(),
+            segments,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    // Returns the captured parameters if the path matches this pattern
+    pub fn matches(&self, path: &str) -> Option<PathParams> {
+        let parts = split_path(path);
+        let mut params = PathParams::new();
+        if match_segments(&self.segments, &parts, &mut params) {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    // Orders patterns from most to least specific, comparing segment by segment
+    pub fn precedence(&self, other: &Self) -> Ordering {
+        let ours = self.segments.iter().map(Segment::rank);
+        let theirs = other.segments.iter().map(Segment::rank);
+        ours.cmp(theirs)
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    let trimmed = path.strip_prefix('/').unwrap_or(path);
+    if trimmed.is_empty() {
+        Vec::new()
+    } else {
+        trimmed.split('/').collect()
+    }
+}
+
+fn validate_name(name: &str, pattern: &str) -> Result<String, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!(
+            "invalid parameter name '{}' in path pattern '{}'",
+            name, pattern
+        ));
+    }
+    Ok(name.to_string())
+}
+
+fn match_segments(segments: &[Segment], parts: &[&str], params: &mut PathParams) -> bool {
+    let Some((segment, rest)) = segments.split_first() else {
+        return parts.is_empty();
+    };
+
+    match segment {
+        Segment::Static(expected) => match parts.split_first() {
+            Some((part, remaining)) if part == expected => match_segments(rest, remaining, params),
+            _ => false,
+        },
+        Segment::Param(name) => match parts.split_first() {
+            Some((part, remaining)) if !part.is_empty() => {
+                params.insert(name.clone(), part.to_string());
+                if match_segments(rest, remaining, params) {
+                    return true;
+                }
+                params.remove(name);
+                false
+            }
+            _ => false,
+        },
+        Segment::Optional(name) => {
+            if let Some((part, remaining)) = parts.split_first() {
+                if !part.is_empty() {
+                    params.insert(name.clone(), part.to_string());
+                    if match_segments(rest, remaining, params) {
+                        return true;
+                    }
+                    params.remove(name);
+                }
+            }
+            match_segments(rest, parts, params)
+        }
+        Segment::Wildcard(name) => {
+            params.insert(name.clone(), parts.join("/"));
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(raw: &str) -> PathPattern {
+        PathPattern::parse(raw).unwrap()
+    }
+
+    fn sorted(raws: &[&str]) -> Vec<String> {
+        let mut patterns: Vec<PathPattern> = raws.iter().map(|raw| pattern(raw)).collect();
+        patterns.sort_by(|a, b| a.precedence(b));
+        patterns.iter().map(|p| p.as_str().to_string()).collect()
+    }
+
+    #[test]
+    fn test_precedence_static_param_optional_wildcard() {
+        let order = sorted(&["/users/*rest", "/users/{id?}", "/users/{id}", "/users/me"]);
+        assert_eq!(order, vec!["/users/me", "/users/{id}", "/users/{id?}", "/users/*rest"]);
+
+        // The first differing segment decides, not the segments after it
+        let order = sorted(&["/{org}/repos/list", "/acme/{repo}/*rest"]);
+        assert_eq!(order, vec!["/acme/{repo}/*rest", "/{org}/repos/list"]);
+
+        assert_eq!(pattern("/a/b").precedence(&pattern("/a/b")), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_params_and_wildcards_capture() {
+        let params = pattern("/users/{id}/posts/{post}").matches("/users/42/posts/7").unwrap();
+        assert_eq!(params["id"], "42");
+        assert_eq!(params["post"], "7");
+
+        let params = pattern("/files/*rest").matches("/files/a/b/c.txt").unwrap();
+        assert_eq!(params["rest"], "a/b/c.txt");
+        let params = pattern("/files/*").matches("/files").unwrap();
+        assert_eq!(params["wildcard"], "");
+
+        assert!(pattern("/users/{id}").matches("/users").is_none());
+        assert!(pattern("/users/{id}").matches("/users/1/extra").is_none());
+    }
+
+    #[test]
+    fn test_empty_segments() {
+        // A parameter never captures an empty segment
+        assert!(pattern("/users/{id}").matches("/users/").is_none());
+        assert!(pattern("/users/{id}/posts").matches("/users//posts").is_none());
+        assert!(pattern("/users/{id?}/posts").matches("/users//posts").is_none());
+
+        // Static segments have to line up exactly, empty ones included
+        assert!(pattern("/a//b").matches("/a//b").is_some());
+        assert!(pattern("/a/b").matches("/a//b").is_none());
+        assert!(pattern("/").matches("/").is_some());
+        assert!(pattern("/").matches("/a").is_none());
+    }
+
+    #[test]
+    fn test_optional_parameter_in_the_middle() {
+        let p = pattern("/teams/{team?}/members");
+
+        let params = p.matches("/teams/red/members").unwrap();
+        assert_eq!(params["team"], "red");
+
+        let params = p.matches("/teams/members").unwrap();
+        assert!(!params.contains_key("team"));
+
+        assert!(p.matches("/teams/red/blue/members").is_none());
+        assert!(p.matches("/teams/red").is_none());
+    }
+
+    #[test]
+    fn test_optional_parameter_backtracks() {
+        // "members" could be the optional value, but then nothing is left for
+        // the static segment, so it must be skipped instead
+        let params = pattern("/{section?}/members").matches("/members").unwrap();
+        assert!(params.is_empty());
+
+        let params = pattern("/{a?}/{b?}").matches("/x").unwrap();
+        assert_eq!(params.get("a").map(String::as_str), Some("x"));
+        assert!(!params.contains_key("b"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(PathPattern::parse("users").is_err());
+        assert!(PathPattern::parse("/users/{id").is_err());
+        assert!(PathPattern::parse("/users/{}").is_err());
+        assert!(PathPattern::parse("/users/{id-x}").is_err());
+        assert!(PathPattern::parse("/users/id{x}").is_err());
+        assert!(PathPattern::parse("/files/*rest/more").is_err());
+    }
+}
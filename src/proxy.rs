@@ -0,0 +1,480 @@
+use crate::body::{self, NoxBody};
+use crate::circuit::{CircuitBreaker, CircuitState};
+use crate::config::{
+    CircuitBreakerConfig, LoadBalancingStrategy, ProxyConfig, ProxyRoute, RetryConfig, UpstreamConfig,
+};
+use crate::context::{Overflow, RequestContext};
+use crate::transform::Transforms;
+use crate::upstream;
+use hyper::{Method, Response, StatusCode};
+use rand::Rng;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_HEALTH_CHECK_INTERVAL: u64 = 10;
+const DEFAULT_MAX_FAILS: u32 = 3;
+const DEFAULT_FAIL_TIMEOUT: u64 = 30;
+const DEFAULT_RETRY_ATTEMPTS: u32 = 2;
+const DEFAULT_RETRY_BACKOFF: u64 = 100;
+const DEFAULT_MAX_BACKOFF: u64 = 2000;
+const DEFAULT_RETRY_STATUSES: &[u16] = &[502, 503, 504];
+
+// Forwards requests under configured path prefixes to pools of upstreams, so
+// proxied paths and mocks can share a port
+pub struct ProxyHandler {
+    pools: Vec<UpstreamPool>,
+    client: upstream::Client,
+    health_check_interval: Duration,
+}
+
+struct UpstreamPool {
+    prefix: String,
+    rewrite: String,
+    strategy: LoadBalancingStrategy,
+    upstreams: Vec<Arc<Upstream>>,
+    next: AtomicUsize,
+    // Running weights for smooth weighted round robin, one per upstream
+    current_weights: Mutex<Vec<i64>>,
+    transforms: Transforms,
+}
+
+pub struct Upstream {
+    config: UpstreamConfig,
+    weight: u32,
+    // Result of the last active health check
+    healthy: AtomicBool,
+    active_connections: AtomicUsize,
+    requests: AtomicU64,
+    consecutive_failures: AtomicU32,
+    // Set by passive health checking after too many failures in a row
+    ejected_until: Mutex<Option<Instant>>,
+    max_fails: u32,
+    fail_timeout: Duration,
+    retry: RetryPolicy,
+    retries: AtomicU64,
+    breaker: Option<CircuitBreaker>,
+}
+
+// Upstreams without a retry config get a single try and no per-try timeout
+#[derive(Debug, Default)]
+struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    per_try_timeout: Option<Duration>,
+    retry_on: Vec<u16>,
+    non_idempotent: bool,
+}
+
+// Shared settings every upstream falls back to
+struct UpstreamDefaults<'a> {
+    max_fails: u32,
+    fail_timeout: Duration,
+    retry: Option<&'a RetryConfig>,
+    circuit_breaker: Option<&'a CircuitBreakerConfig>,
+}
+
+// Counts an in-flight request against its upstream until dropped
+struct ConnectionGuard(Arc<Upstream>);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub prefix: String,
+    pub rewrite: String,
+    pub strategy: LoadBalancingStrategy,
+    pub upstreams: Vec<UpstreamStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStats {
+    pub name: String,
+    pub url: String,
+    pub weight: u32,
+    pub healthy: bool,
+    pub ejected: bool,
+    pub active_connections: usize,
+    pub requests: u64,
+    pub consecutive_failures: u32,
+    pub retries: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<CircuitState>,
+}
+
+impl ProxyHandler {
+    pub fn new(config: &ProxyConfig) -> Self {
+        let defaults = UpstreamDefaults {
+            max_fails: config.max_fails.unwrap_or(DEFAULT_MAX_FAILS),
+            fail_timeout: Duration::from_secs(config.fail_timeout.unwrap_or(DEFAULT_FAIL_TIMEOUT)),
+            retry: config.retry.as_ref(),
+            circuit_breaker: config.circuit_breaker.as_ref(),
+        };
+        let timeout = config.timeout.map_or(upstream::DEFAULT_UPSTREAM_TIMEOUT, Duration::from_secs);
+
+        let mut pools: Vec<UpstreamPool> = config
+            .routes
+            .iter()
+            .filter_map(|route| match UpstreamPool::new(route, &defaults) {
+                Ok(pool) => Some(pool),
+                Err(err) => {
+                    eprintln!("Skipping proxy route {}: {}", route.prefix, err);
+                    None
+                }
+            })
+            .collect();
+        // Longest prefix first so nested prefixes win over their parents
+        pools.sort_by_key(|pool| std::cmp::Reverse(pool.prefix.len()));
+
+        Self {
+            pools,
+            client: upstream::Client::new(timeout),
+            health_check_interval: Duration::from_secs(
+                config.health_check_interval.unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL),
+            ),
+        }
+    }
+
+    // Returns None when the path is outside every proxied prefix, leaving
+    // `overflow` for whoever handles the request next
+    pub async fn handle(&self, ctx: &RequestContext, overflow: &mut Option<Overflow>) -> Option<Response<NoxBody>> {
+        let (pool, rest) = self.pools.iter().find_map(|pool| Some((pool, pool.strip(ctx.path())?)))?;
+        // A streamed body is gone once sent, so it gets a single try
+        let streamed = overflow.is_some();
+
+        let mut retries = 0;
+        loop {
+            let Some(upstream) = pool.select() else {
+                return Some(
+                    Response::builder()
+                        .status(StatusCode::SERVICE_UNAVAILABLE)
+                        .body(body::full(format!("No healthy upstream for {}", pool.prefix)))
+                        .unwrap(),
+                );
+            };
+
+            let url = format!(
+                "{}{}{}{}",
+                upstream.config.url.trim_end_matches('/'),
+                pool.rewrite,
+                rest,
+                ctx.uri.query().map(|q| format!("?{}", q)).unwrap_or_default()
+            );
+            let guard = upstream.connect();
+            let result = upstream.send(&self.client, &url, ctx, overflow.take()).await;
+
+            let (failed, retryable) = match &result {
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    (is_gateway_error(status), upstream.retry.retry_on.contains(&status))
+                }
+                Err(_) => (true, true),
+            };
+            if failed {
+                upstream.record_failure();
+            } else {
+                upstream.record_success();
+            }
+
+            // With nowhere left to retry, the upstream's own answer beats a 503
+            if retryable && !streamed && upstream.retry.allows(retries, &ctx.method) && pool.has_available() {
+                retries += 1;
+                upstream.retries.fetch_add(1, Ordering::Relaxed);
+                drop(guard);
+                tokio::time::sleep(upstream.retry.backoff(retries)).await;
+                continue;
+            }
+
+            return Some(match result {
+                Ok(response) => {
+                    let response = upstream::streamed(response, &upstream.config.name, guard);
+                    pool.transforms.apply(response).await
+                }
+                Err(response) => response,
+            });
+        }
+    }
+
+    // Probes every upstream that has a health_check path on a fixed interval,
+    // until the returned task is aborted
+    pub fn start_health_checks(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let upstreams: Vec<Arc<Upstream>> = self
+            .pools
+            .iter()
+            .flat_map(|pool| pool.upstreams.iter())
+            .filter(|upstream| upstream.config.health_check.is_some())
+            .cloned()
+            .collect();
+        if upstreams.is_empty() {
+            return None;
+        }
+
+        let client = self.client.clone();
+        let interval = self.health_check_interval;
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                for upstream in &upstreams {
+                    let path = upstream.config.health_check.as_deref().unwrap_or("/");
+                    let url = format!("{}{}", upstream.config.url.trim_end_matches('/'), path);
+                    let healthy = matches!(client.get(&url).await, Ok(r) if r.status().is_success());
+                    if upstream.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                        let state = if healthy { "healthy" } else { "unhealthy" };
+                        println!("Upstream {} is {}", upstream.config.name, state);
+                    }
+                }
+            }
+        }))
+    }
+
+    pub fn stats(&self) -> Vec<PoolStats> {
+        self.pools
+            .iter()
+            .map(|pool| PoolStats {
+                prefix: pool.prefix.clone(),
+                rewrite: pool.rewrite.clone(),
+                strategy: pool.strategy,
+                upstreams: pool.upstreams.iter().map(|u| u.stats()).collect(),
+            })
+            .collect()
+    }
+}
+
+impl UpstreamPool {
+    fn new(route: &ProxyRoute, defaults: &UpstreamDefaults) -> Result<Self, String> {
+        if route.upstreams.is_empty() {
+            return Err("no upstreams".to_string());
+        }
+        let transforms = Transforms::compile(route.transform.as_deref().unwrap_or_default())?;
+
+        let prefix = normalize_prefix(&route.prefix);
+        let rewrite = route.rewrite.as_deref().map_or_else(|| prefix.clone(), normalize_prefix);
+        let upstreams: Vec<Arc<Upstream>> = route
+            .upstreams
+            .iter()
+            .map(|config| Arc::new(Upstream::new(config, defaults)))
+            .collect();
+
+        Ok(Self {
+            prefix,
+            rewrite,
+            strategy: route.strategy.unwrap_or_default(),
+            current_weights: Mutex::new(vec![0; upstreams.len()]),
+            upstreams,
+            next: AtomicUsize::new(0),
+            transforms,
+        })
+    }
+
+    // The rest of the path after the prefix, if the path is under it
+    fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(self.prefix.as_str())?;
+        (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+    }
+
+    fn has_available(&self) -> bool {
+        self.upstreams.iter().any(|upstream| upstream.is_available())
+    }
+
+    fn select(&self) -> Option<Arc<Upstream>> {
+        let available: Vec<usize> = (0..self.upstreams.len())
+            .filter(|&i| self.upstreams[i].is_available())
+            .collect();
+        if available.is_empty() {
+            return None;
+        }
+
+        let index = match self.strategy {
+            LoadBalancingStrategy::RoundRobin => {
+                available[self.next.fetch_add(1, Ordering::Relaxed) % available.len()]
+            }
+            LoadBalancingStrategy::LeastConnections => *available
+                .iter()
+                .min_by_key(|&&i| self.upstreams[i].active_connections.load(Ordering::Relaxed))
+                .unwrap(),
+            LoadBalancingStrategy::WeightedRoundRobin => self.select_weighted(&available),
+            LoadBalancingStrategy::Random => available[rand::thread_rng().gen_range(0..available.len())],
+        };
+        let upstream = &self.upstreams[index];
+        // Another request may have taken the last half-open trial since the check above
+        upstream.breaker.as_ref().is_none_or(|b| b.acquire()).then(|| Arc::clone(upstream))
+    }
+
+    // Smooth weighted round robin: every pick raises each upstream's running
+    // weight by its configured weight, takes the highest and lowers it by the
+    // total, which interleaves upstreams instead of sending bursts
+    fn select_weighted(&self, available: &[usize]) -> usize {
+        let mut current = self.current_weights.lock().unwrap();
+        let mut total = 0;
+        let mut best = available[0];
+        for &i in available {
+            let weight = i64::from(self.upstreams[i].weight);
+            current[i] += weight;
+            total += weight;
+            if current[i] > current[best] {
+                best = i;
+            }
+        }
+        current[best] -= total;
+        best
+    }
+}
+
+impl Upstream {
+    fn new(config: &UpstreamConfig, defaults: &UpstreamDefaults) -> Self {
+        let breaker = config.circuit_breaker.as_ref().or(defaults.circuit_breaker);
+        Self {
+            config: config.clone(),
+            weight: config.weight.unwrap_or(1).max(1),
+            healthy: AtomicBool::new(true),
+            active_connections: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            consecutive_failures: AtomicU32::new(0),
+            ejected_until: Mutex::new(None),
+            max_fails: defaults.max_fails,
+            fail_timeout: defaults.fail_timeout,
+            retry: config.retry.as_ref().or(defaults.retry).map(RetryPolicy::new).unwrap_or_default(),
+            retries: AtomicU64::new(0),
+            breaker: breaker.map(|breaker| CircuitBreaker::new(&config.name, breaker)),
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+            && !self.is_ejected()
+            && self.breaker.as_ref().is_none_or(|b| b.permits())
+    }
+
+    // Errors come back as the response to give the client: 504 when the
+    // per-try timeout ran out, 502 for anything else
+    async fn send(
+        &self,
+        client: &upstream::Client,
+        url: &str,
+        ctx: &RequestContext,
+        overflow: Option<Overflow>,
+    ) -> Result<reqwest::Response, Response<NoxBody>> {
+        let request = client.send(url, ctx, overflow);
+        let result = match self.retry.per_try_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, request).await {
+                Ok(result) => result,
+                Err(_) => {
+                    eprintln!("Upstream {} timed out after {}ms", self.config.name, timeout.as_millis());
+                    return Err(Response::builder()
+                        .status(StatusCode::GATEWAY_TIMEOUT)
+                        .body(body::full(format!("Upstream {} timed out", self.config.name)))
+                        .unwrap());
+                }
+            },
+            None => request.await,
+        };
+        result.map_err(|err| upstream::bad_gateway(&err))
+    }
+
+    fn is_ejected(&self) -> bool {
+        self.ejected_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    fn connect(self: &Arc<Self>) -> ConnectionGuard {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(Arc::clone(self))
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        if let Some(breaker) = &self.breaker {
+            breaker.record_success();
+        }
+    }
+
+    fn record_failure(&self) {
+        if let Some(breaker) = &self.breaker {
+            breaker.record_failure();
+        }
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.max_fails > 0 && failures >= self.max_fails {
+            eprintln!(
+                "Ejecting upstream {} for {}s after {} failures",
+                self.config.name,
+                self.fail_timeout.as_secs(),
+                failures
+            );
+            *self.ejected_until.lock().unwrap() = Some(Instant::now() + self.fail_timeout);
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn stats(&self) -> UpstreamStats {
+        UpstreamStats {
+            name: self.config.name.clone(),
+            url: self.config.url.clone(),
+            weight: self.weight,
+            healthy: self.healthy.load(Ordering::Relaxed),
+            ejected: self.is_ejected(),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            consecutive_failures: self.consecutive_failures.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            circuit: self.breaker.as_ref().map(|b| b.state()),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn new(config: &RetryConfig) -> Self {
+        Self {
+            attempts: config.attempts.unwrap_or(DEFAULT_RETRY_ATTEMPTS),
+            backoff: Duration::from_millis(config.backoff.unwrap_or(DEFAULT_RETRY_BACKOFF)),
+            max_backoff: Duration::from_millis(config.max_backoff.unwrap_or(DEFAULT_MAX_BACKOFF)),
+            per_try_timeout: config.per_try_timeout.map(Duration::from_millis),
+            retry_on: config.retry_on.clone().unwrap_or_else(|| DEFAULT_RETRY_STATUSES.to_vec()),
+            non_idempotent: config.non_idempotent.unwrap_or(false),
+        }
+    }
+
+    // POST and PATCH may have had an effect even when they failed, so they are
+    // only retried when the config says so
+    fn allows(&self, retries: u32, method: &Method) -> bool {
+        retries < self.attempts && (self.non_idempotent || is_idempotent(method))
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// "/api/" and "/api" are the same prefix; "/" stays as the catch-all
+fn normalize_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim_end_matches('/');
+    if trimmed.starts_with('/') {
+        trimmed.to_string()
+    } else if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    )
+}
+
+// Responses that say the upstream itself is in trouble count towards ejection
+fn is_gateway_error(status: u16) -> bool {
+    matches!(status, 502..=504)
+}
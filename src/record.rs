@@ -0,0 +1,503 @@
+use crate::body::{self, NoxBody};
+use crate::config::{BodyMatcher, DedupeMode, MockResponse, MockRoute, NoxConfig, RecordConfig, ValueMatcher};
+use crate::context::RequestContext;
+use crate::error::{Error, Result};
+use crate::upstream;
+use bytes::Bytes;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::Response;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const DEFAULT_SCENARIO: &str = "recorded";
+const DEFAULT_STRIP_HEADERS: &[&str] = &["authorization", "cookie", "set-cookie", "date", "x-request-id"];
+// Numbers, UUIDs and long hex strings
+const DEFAULT_ID_PATTERN: &str =
+    r"^(\d+|[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}|[0-9a-fA-F]{16,})$";
+// Shorter ids are too likely to appear in a body by accident to template them
+const MIN_BODY_ID_LEN: usize = 4;
+
+// Forwards unmatched requests to a real upstream and writes every exchange
+// out as a scenario that can be loaded back with `NoxConfig::load_from_file`
+pub struct Recorder {
+    upstream: String,
+    output: PathBuf,
+    scenario: String,
+    strip_headers: Vec<String>,
+    match_headers: Vec<String>,
+    id_pattern: Option<Regex>,
+    dedupe: DedupeMode,
+    client: upstream::Client,
+    captures: Mutex<Captures>,
+    // Version of the captures last written out, so a slow write can't replace
+    // a newer one
+    written: tokio::sync::Mutex<u64>,
+}
+
+#[derive(Default)]
+struct Captures {
+    routes: Vec<(String, MockRoute)>,
+    body_files: usize,
+    version: u64,
+}
+
+// What a capture leaves to write once the captures lock is released
+struct PendingWrite {
+    version: u64,
+    yaml: String,
+    body_file: Option<PathBuf>,
+}
+
+impl Recorder {
+    pub fn new(config: &RecordConfig) -> std::result::Result<Self, String> {
+        let id_pattern = if config.templatize_ids.unwrap_or(true) {
+            let pattern = config.id_pattern.as_deref().unwrap_or(DEFAULT_ID_PATTERN);
+            Some(Regex::new(pattern).map_err(|e| format!("invalid id_pattern '{}': {}", pattern, e))?)
+        } else {
+            None
+        };
+
+        let lowercase = |names: &[String]| names.iter().map(|n| n.to_ascii_lowercase()).collect::<Vec<_>>();
+        let strip_headers = match &config.strip_headers {
+            Some(names) => lowercase(names),
+            None => DEFAULT_STRIP_HEADERS.iter().map(|n| n.to_string()).collect(),
+        };
+
+        Ok(Self {
+            upstream: config.upstream.clone(),
+            output: config.output.clone(),
+            scenario: config.scenario.clone().unwrap_or_else(|| DEFAULT_SCENARIO.to_string()),
+            match_headers: lowercase(config.match_headers.as_deref().unwrap_or_default()),
+            strip_headers,
+            id_pattern,
+            dedupe: config.dedupe.unwrap_or_default(),
+            client: upstream::Client::new(upstream::DEFAULT_UPSTREAM_TIMEOUT),
+            captures: Mutex::new(Captures::default()),
+            written: tokio::sync::Mutex::new(0),
+        })
+    }
+
+    pub async fn forward(&self, ctx: &RequestContext) -> Response<NoxBody> {
+        let url = upstream::target_url(&self.upstream, ctx);
+        let upstream_response = match self.client.send(&url, ctx, None).await {
+            Ok(response) => response,
+            Err(err) => return upstream::bad_gateway(&err),
+        };
+
+        let builder = upstream::response_builder(&upstream_response, &self.upstream);
+        let status = upstream_response.status().as_u16();
+        let headers: Vec<(String, String)> = upstream_response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !upstream::is_hop_by_hop_header(name.as_str()))
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = match upstream_response.bytes().await {
+            Ok(body) => body,
+            Err(err) => return upstream::bad_gateway(&err),
+        };
+
+        let written = match self.capture(ctx, status, &headers, &body) {
+            Ok(Some(pending)) => self.write(pending, &body).await,
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = written {
+            eprintln!("Failed to record {} {}: {}", ctx.method, ctx.path(), err);
+        }
+
+        builder.body(body::full(body)).unwrap()
+    }
+
+    // Adds the exchange to the captures and returns what needs writing, or
+    // None when de-duplication dropped it. No file I/O happens under the lock
+    fn capture(
+        &self,
+        ctx: &RequestContext,
+        status: u16,
+        headers: &[(String, String)],
+        body: &Bytes,
+    ) -> Result<Option<PendingWrite>> {
+        let (path, ids) = self.templatize_path(ctx.path());
+
+        // JSON bodies are compared in canonical form so formatting doesn't defeat de-duplication
+        let body_key = match ctx.json_body() {
+            Some(json) => json.to_string(),
+            None => ctx.body_text().into_owned(),
+        };
+        let key = format!("{} {}?{} {}", ctx.method, path, ctx.uri.query().unwrap_or_default(), body_key);
+
+        let mut captures = self.captures.lock().unwrap();
+        let seen = captures.routes.iter().any(|(k, _)| *k == key);
+        if seen && self.dedupe == DedupeMode::KeepFirst {
+            return Ok(None);
+        }
+
+        let kept: HashMap<String, String> = headers
+            .iter()
+            .filter(|(name, _)| !self.strip_headers.contains(name) && name != CONTENT_LENGTH.as_str())
+            .cloned()
+            .collect();
+        let mut response = MockResponse {
+            status,
+            headers: (!kept.is_empty()).then_some(kept),
+            ..Default::default()
+        };
+
+        let mut body_file = None;
+        match std::str::from_utf8(body) {
+            Ok(text) => response.body = text.to_string(),
+            Err(_) => {
+                captures.body_files += 1;
+                let content_type = headers
+                    .iter()
+                    .find(|(name, _)| name == CONTENT_TYPE.as_str())
+                    .map(|(_, value)| value.as_str());
+                let relative = self.body_file_path(captures.body_files, content_type);
+                body_file = Some(output_dir(&self.output).join(&relative));
+                response.body_file = Some(relative);
+            }
+        }
+        template_ids(&mut response, &ids);
+
+        let existing = captures.routes.iter_mut().find(|(k, _)| *k == key);
+        match (existing, self.dedupe) {
+            (Some((_, route)), DedupeMode::KeepLast) => route.response = Some(response),
+            (Some((_, route)), DedupeMode::Sequence) => {
+                let sequence = route.sequence.get_or_insert_with(Vec::new);
+                sequence.extend(route.response.take());
+                sequence.push(response);
+            }
+            _ => {
+                let route = self.route_for(ctx, path, response);
+                captures.routes.push((key, route));
+            }
+        }
+
+        captures.version += 1;
+        Ok(Some(PendingWrite {
+            version: captures.version,
+            yaml: self.scenario_yaml(&captures)?,
+            body_file,
+        }))
+    }
+
+    // Replaces id-like path segments with {id}, {id2}, ... and returns the
+    // placeholder names alongside the values they replaced
+    fn templatize_path(&self, path: &str) -> (String, Vec<(String, String)>) {
+        let Some(pattern) = &self.id_pattern else {
+            return (path.to_string(), Vec::new());
+        };
+
+        let mut ids = Vec::new();
+        let segments: Vec<String> = path
+            .split('/')
+            .map(|segment| {
+                if segment.is_empty() || !pattern.is_match(segment) {
+                    return segment.to_string();
+                }
+                let name = match ids.len() {
+                    0 => "id".to_string(),
+                    n => format!("id{}", n + 1),
+                };
+                ids.push((name.clone(), segment.to_string()));
+                format!("{{{}}}", name)
+            })
+            .collect();
+        (segments.join("/"), ids)
+    }
+
+    fn route_for(&self, ctx: &RequestContext, path: String, response: MockResponse) -> MockRoute {
+        let exact = |value: &str| ValueMatcher::Exact(Value::String(value.to_string()));
+
+        let mut query = HashMap::new();
+        for (name, value) in &ctx.query {
+            query.entry(name.clone()).or_insert_with(|| exact(value));
+        }
+
+        let mut headers = HashMap::new();
+        for name in &self.match_headers {
+            if let Some(value) = ctx.header_values(name).next() {
+                headers.insert(name.clone(), exact(value));
+            }
+        }
+
+        // JSON bodies are matched structurally so key order and spacing don't matter
+        let body = match ctx.json_body() {
+            _ if ctx.body.is_empty() => None,
+            Some(json) => Some(BodyMatcher {
+                json_partial: Some(json.clone()),
+                ..Default::default()
+            }),
+            None => Some(BodyMatcher {
+                equals: Some(ctx.body_text().into_owned()),
+                ..Default::default()
+            }),
+        };
+
+        MockRoute {
+            path,
+            method: ctx.method.to_string(),
+            headers: (!headers.is_empty()).then_some(headers),
+            query: (!query.is_empty()).then_some(query),
+            body,
+            response: Some(response),
+            ..Default::default()
+        }
+    }
+
+    // Binary bodies go in a directory next to the output file and are
+    // referenced relative to it
+    fn body_file_path(&self, index: usize, content_type: Option<&str>) -> PathBuf {
+        let stem = self.output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let extension = content_type
+            .and_then(|ct| mime_guess::get_mime_extensions_str(ct.split(';').next().unwrap_or_default()))
+            .and_then(|extensions| extensions.first())
+            .unwrap_or(&"bin");
+        PathBuf::from(format!("{}_bodies", stem)).join(format!("{}.{}", index, extension))
+    }
+
+    fn scenario_yaml(&self, captures: &Captures) -> Result<String> {
+        let routes: Vec<&MockRoute> = captures.routes.iter().map(|(_, route)| route).collect();
+        let config = serde_json::json!({
+            "server": NoxConfig::default().server,
+            "mock": {
+                "scenarios": [{ "name": self.scenario, "routes": routes }],
+            },
+        });
+        serde_yaml::to_string(&without_nulls(config)).map_err(|e| Error::Other(e.to_string()))
+    }
+
+    // The scenario is rewritten after every capture so nothing is lost if the
+    // server is killed
+    async fn write(&self, pending: PendingWrite, body: &Bytes) -> Result<()> {
+        if let Some(path) = &pending.body_file {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(path, body).await?;
+        }
+
+        let mut written = self.written.lock().await;
+        if pending.version > *written {
+            tokio::fs::create_dir_all(output_dir(&self.output)).await?;
+            tokio::fs::write(&self.output, pending.yaml).await?;
+            *written = pending.version;
+        }
+        Ok(())
+    }
+}
+
+// Turns captured id values back into path placeholders wherever they appear in
+// the response, so one recording serves every id
+fn template_ids(response: &mut MockResponse, ids: &[(String, String)]) {
+    let ids: Vec<(Regex, String)> = ids
+        .iter()
+        .filter(|(_, value)| value.len() >= MIN_BODY_ID_LEN)
+        .filter_map(|(name, value)| {
+            let regex = Regex::new(&format!(r"\b{}\b", regex::escape(value))).ok()?;
+            Some((regex, format!("{{{{path.{}}}}}", name)))
+        })
+        .collect();
+
+    // Bodies that already look like templates are left alone
+    let has_braces = response.body.contains("{{")
+        || response.headers.iter().flatten().any(|(_, value)| value.contains("{{"));
+    if ids.is_empty() || has_braces {
+        return;
+    }
+
+    let mut templated = false;
+    let mut replace = |text: &mut String| {
+        for (regex, placeholder) in &ids {
+            if regex.is_match(text) {
+                *text = regex.replace_all(text, placeholder.as_str()).into_owned();
+                templated = true;
+            }
+        }
+    };
+    replace(&mut response.body);
+    for value in response.headers.iter_mut().flat_map(|h| h.values_mut()) {
+        replace(value);
+    }
+
+    if templated {
+        response.template = Some(true);
+    }
+}
+
+fn output_dir(output: &Path) -> &Path {
+    output.parent().unwrap_or(Path::new("."))
+}
+
+// Unset options serialize as nulls; drop them to keep the file readable
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, without_nulls(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(without_nulls).collect()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{HeaderMap, Method};
+    use tempfile::TempDir;
+
+    fn recorder(output: &Path, dedupe: DedupeMode, templatize_ids: bool) -> Recorder {
+        Recorder::new(&RecordConfig {
+            upstream: "http://127.0.0.1:9".to_string(),
+            output: output.to_path_buf(),
+            scenario: None,
+            strip_headers: None,
+            match_headers: None,
+            templatize_ids: Some(templatize_ids),
+            id_pattern: None,
+            dedupe: Some(dedupe),
+        })
+        .unwrap()
+    }
+
+    fn request(path: &str) -> RequestContext {
+        RequestContext::new(Method::GET, path.parse().unwrap(), HeaderMap::new(), Bytes::new())
+    }
+
+    fn response(body: &str) -> MockResponse {
+        MockResponse {
+            status: 200,
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Captures the same call three times with responses a, b and c
+    fn captured(dedupe: DedupeMode) -> Vec<MockRoute> {
+        let dir = TempDir::new().unwrap();
+        let recorder = recorder(&dir.path().join("rec.yaml"), dedupe, true);
+        for body in ["a", "b", "c"] {
+            recorder.capture(&request("/items/1"), 200, &[], &Bytes::from(body)).unwrap();
+        }
+        let captures = recorder.captures.lock().unwrap();
+        captures.routes.iter().map(|(_, route)| route.clone()).collect()
+    }
+
+    fn bodies(route: &MockRoute) -> Vec<&str> {
+        route.response.iter().chain(route.sequence.iter().flatten()).map(|r| r.body.as_str()).collect()
+    }
+
+    #[test]
+    fn test_templatize_path() {
+        let dir = TempDir::new().unwrap();
+        let templating = recorder(&dir.path().join("rec.yaml"), DedupeMode::KeepFirst, true);
+
+        let (path, ids) = templating.templatize_path("/v1/users/42/orders/550e8400-e29b-41d4-a716-446655440000");
+        assert_eq!(path, "/v1/users/{id}/orders/{id2}");
+        assert_eq!(
+            ids,
+            [
+                ("id".to_string(), "42".to_string()),
+                ("id2".to_string(), "550e8400-e29b-41d4-a716-446655440000".to_string())
+            ]
+        );
+        assert_eq!(templating.templatize_path("/users/me").0, "/users/me");
+
+        let plain = recorder(&dir.path().join("rec.yaml"), DedupeMode::KeepFirst, false);
+        assert_eq!(plain.templatize_path("/users/42"), ("/users/42".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn test_template_ids() {
+        let ids = [("id".to_string(), "12345".to_string()), ("id2".to_string(), "42".to_string())];
+
+        let mut templated = response(r#"{"id": 12345, "ref": "x-12345", "other": 123456, "n": 42}"#);
+        template_ids(&mut templated, &ids);
+        assert_eq!(templated.body, r#"{"id": {{path.id}}, "ref": "x-{{path.id}}", "other": 123456, "n": 42}"#);
+        assert_eq!(templated.template, Some(true));
+
+        // Bodies that already use braces are left as they are
+        let mut braces = response("{{literal}} 12345");
+        template_ids(&mut braces, &ids);
+        assert_eq!(braces.body, "{{literal}} 12345");
+        assert_eq!(braces.template, None);
+
+        let mut untouched = response("nothing here");
+        template_ids(&mut untouched, &ids);
+        assert_eq!(untouched.template, None);
+    }
+
+    #[test]
+    fn test_dedupe_keep_first() {
+        let routes = captured(DedupeMode::KeepFirst);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].path, "/items/{id}");
+        assert_eq!(bodies(&routes[0]), ["a"]);
+    }
+
+    #[test]
+    fn test_dedupe_keep_last() {
+        let routes = captured(DedupeMode::KeepLast);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(bodies(&routes[0]), ["c"]);
+    }
+
+    #[test]
+    fn test_dedupe_sequence() {
+        let routes = captured(DedupeMode::Sequence);
+        assert_eq!(routes.len(), 1);
+        assert!(routes[0].response.is_none());
+        assert_eq!(bodies(&routes[0]), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_dedupe_off() {
+        let routes = captured(DedupeMode::Off);
+        assert_eq!(routes.iter().map(|r| bodies(r)[0]).collect::<Vec<_>>(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_keep_first_skips_the_write() {
+        let dir = TempDir::new().unwrap();
+        let recorder = recorder(&dir.path().join("rec.yaml"), DedupeMode::KeepFirst, true);
+        let first = recorder.capture(&request("/items/1"), 200, &[], &Bytes::from("a")).unwrap();
+        let second = recorder.capture(&request("/items/1"), 200, &[], &Bytes::from("b")).unwrap();
+        assert_eq!(first.map(|p| p.version), Some(1));
+        assert!(second.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_older_snapshot_does_not_overwrite_newer() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("out/rec.yaml");
+        let recorder = recorder(&output, DedupeMode::Off, true);
+        let older = recorder.capture(&request("/a"), 200, &[], &Bytes::from("a")).unwrap().unwrap();
+        let newer = recorder.capture(&request("/b"), 200, &[], &Bytes::from("b")).unwrap().unwrap();
+
+        recorder.write(newer, &Bytes::new()).await.unwrap();
+        recorder.write(older, &Bytes::new()).await.unwrap();
+        let config = NoxConfig::load_from_file(output.to_str().unwrap()).unwrap();
+        let routes = &config.mock.unwrap().scenarios[0].routes;
+        assert_eq!(routes.iter().map(|r| r.path.as_str()).collect::<Vec<_>>(), ["/a", "/b"]);
+    }
+
+    #[tokio::test]
+    async fn test_binary_body_is_written_to_a_file() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("rec.yaml");
+        let recorder = recorder(&output, DedupeMode::Off, true);
+        let body = Bytes::from_static(&[0xff, 0x00, 0xfe]);
+        let headers = [("content-type".to_string(), "image/png".to_string())];
+
+        let pending = recorder.capture(&request("/logo"), 200, &headers, &body).unwrap().unwrap();
+        recorder.write(pending, &body).await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("rec_bodies/1.png")).unwrap(), body);
+    }
+}
@@ -1,25 +1,202 @@
-use crate::config::{MockConfig, MockRoute, MockResponse};
+use crate::admin;
+use crate::body::{self, NoxBody};
+use crate::config::{DelaySpec, Fault, MockConfig, MockRoute, MockResponse, MockScenario, SequenceMode};
+use crate::context::{BodyError, Overflow, RequestContext, DEFAULT_MAX_BODY_SIZE};
+use crate::error::Error;
+use crate::fault;
+use crate::journal::{Journal, DEFAULT_JOURNAL_BODY_LIMIT, DEFAULT_JOURNAL_SIZE};
+use crate::matcher::RequestMatcher;
+use crate::pattern::{PathParams, PathPattern};
+use crate::static_files::StaticFileHandler;
 use hyper::{Request, Response, Method, StatusCode};
 use hyper::body::Incoming;
-use http_body_util::Full;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use bytes::Bytes;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::fmt::Display;
+use std::path::{Component, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+#[cfg(feature = "templates")]
+use crate::template::TemplateEngine;
+
+#[cfg(feature = "proxy")]
+use crate::proxy::ProxyHandler;
+#[cfg(feature = "proxy")]
+use crate::record::Recorder;
+#[cfg(feature = "proxy")]
+use crate::upstream::Passthrough;
 
 pub struct MockRouter {
-    routes: Vec<RouteMatcher>,
+    routes: RwLock<Vec<RouteMatcher>>,
+    next_route_id: AtomicUsize,
+    max_body_size: usize,
+    default_delay: Option<DelaySpec>,
+    file_cache: Mutex<HashMap<PathBuf, Bytes>>,
+    rng: Mutex<StdRng>,
+    // Locked around a candidate route's scenario check and state transition,
+    // so those and enable/disable switches are atomic with respect to each
+    // other. Path and request matching run without it
+    scenarios: Mutex<Vec<Scenario>>,
+    journal: Journal,
+    verbose_not_found: bool,
+    // Longest prefix first
+    static_files: Vec<StaticFileHandler>,
+    #[cfg(feature = "proxy")]
+    recorder: Option<Recorder>,
+    #[cfg(feature = "proxy")]
+    proxy: Option<ProxyHandler>,
+    #[cfg(feature = "proxy")]
+    fallback: Option<Passthrough>,
+    #[cfg(feature = "templates")]
+    templates: TemplateEngine,
 }
 
 #[derive(Debug, Clone)]
 struct RouteMatcher {
-    path_pattern: String,
+    id: String,
+    source: RouteSource,
+    path_pattern: PathPattern,
     method: Method,
-    response: MockResponse,
+    request: RequestMatcher,
+    responses: Responses,
+    scenario: Option<String>,
+    required_state: Option<String>,
+    definition: Option<MockRoute>,
+}
+
+// Where a route came from; runtime routes can be cleared without touching the rest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteSource {
+    Builtin,
+    Config,
+    Runtime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteInfo {
+    pub id: String,
+    pub method: String,
+    pub path: String,
+    pub source: RouteSource,
+    pub scenario: Option<String>,
+    pub definition: Option<MockRoute>,
+}
+
+pub const DEFAULT_SCENARIO_STATE: &str = "Started";
+
+#[derive(Debug, Clone)]
+struct Scenario {
+    name: String,
+    group: Option<String>,
+    enabled: bool,
+    state: String,
+    initial_state: String,
+    hosts: Vec<String>,
+    listeners: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScenarioStatus {
+    pub name: String,
+    pub group: Option<String>,
+    pub enabled: bool,
+    pub state: String,
+    pub initial_state: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScenarioError {
+    UnknownScenario,
+    UnknownGroup,
+    NotInGroup,
+}
+
+#[derive(Debug, Clone)]
+enum Responses {
+    Single(Arc<MockResponse>),
+    Weighted {
+        responses: Vec<Arc<MockResponse>>,
+        weights: WeightedIndex<u32>,
+    },
+    Sequence {
+        responses: Vec<Arc<MockResponse>>,
+        mode: SequenceMode,
+        // Shared by clones so concurrent connections see a single count
+        calls: Arc<AtomicUsize>,
+        exhausted: Arc<MockResponse>,
+    },
+}
+
+const MAX_CANDIDATES: usize = 3;
+
+// A route that came close to matching an unmatched request, and why it didn't
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    pub id: String,
+    pub method: String,
+    pub path: String,
+    pub scenario: Option<String>,
+    pub reason: String,
+    #[serde(skip)]
+    stage: Stage,
+    #[serde(skip)]
+    distance: usize,
+}
+
+// How far a route got through matching before it failed, least promising first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Path,
+    Method,
+    Scenario,
+    State,
+    Request,
+}
+
+// A successful route lookup along with the values captured from the path. It
+// owns what it needs so the route table can change while the response is built
+#[derive(Debug)]
+pub struct RouteMatch {
+    pub id: String,
+    pub pattern: String,
+    pub params: PathParams,
+    pub response: Arc<MockResponse>,
 }
 
 impl MockRouter {
     pub fn new() -> Self {
         let mut router = Self {
-            routes: Vec::new(),
+            routes: RwLock::new(Vec::new()),
+            next_route_id: AtomicUsize::new(1),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            default_delay: None,
+            file_cache: Mutex::new(HashMap::new()),
+            rng: Mutex::new(StdRng::from_entropy()),
+            scenarios: Mutex::new(Vec::new()),
+            journal: Journal::default(),
+            verbose_not_found: true,
+            static_files: Vec::new(),
+            #[cfg(feature = "proxy")]
+            recorder: None,
+            #[cfg(feature = "proxy")]
+            proxy: None,
+            #[cfg(feature = "proxy")]
+            fallback: None,
+            #[cfg(feature = "templates")]
+            templates: TemplateEngine::new(),
         };
         
         // Add default routes
@@ -32,21 +209,35 @@ impl MockRouter {
         use std::collections::HashMap;
 
         // Default health endpoint
-        self.routes.push(RouteMatcher {
-            path_pattern: "/health".to_string(),
+        self.insert_route(RouteMatcher {
+            path_pattern: PathPattern::parse("/health").unwrap(),
             method: Method::GET,
-            response: MockResponse {
+            request: RequestMatcher::default(),
+            id: String::new(),
+            source: RouteSource::Builtin,
+            scenario: None,
+            required_state: None,
+            definition: None,
+            responses: Responses::Single(Arc::new(MockResponse {
                 status: 200,
                 headers: None,
                 body: "OK".to_string(),
-            },
-        });
+                ..Default::default()
+            })),
+        })
+        .unwrap();
 
         // Default root endpoint
-        self.routes.push(RouteMatcher {
-            path_pattern: "/".to_string(),
+        self.insert_route(RouteMatcher {
+            path_pattern: PathPattern::parse("/").unwrap(),
             method: Method::GET,
-            response: MockResponse {
+            request: RequestMatcher::default(),
+            id: String::new(),
+            source: RouteSource::Builtin,
+            scenario: None,
+            required_state: None,
+            definition: None,
+            responses: Responses::Single(Arc::new(MockResponse {
                 status: 200,
                 headers: Some({
                     let mut headers = HashMap::new();
@@ -54,14 +245,22 @@ impl MockRouter {
                     headers
                 }),
                 body: "NOX Server - Mock Ready".to_string(),
-            },
-        });
+                ..Default::default()
+            })),
+        })
+        .unwrap();
 
         // Secret handshake endpoint for kick <-> nox identification
-        self.routes.push(RouteMatcher {
-            path_pattern: "/nox/handshake".to_string(),
+        self.insert_route(RouteMatcher {
+            path_pattern: PathPattern::parse("/nox/handshake").unwrap(),
             method: Method::GET,
-            response: MockResponse {
+            request: RequestMatcher::default(),
+            id: String::new(),
+            source: RouteSource::Builtin,
+            scenario: None,
+            required_state: None,
+            definition: None,
+            responses: Responses::Single(Arc::new(MockResponse {
                 status: 200,
                 headers: Some({
                     let mut headers = HashMap::new();
@@ -70,76 +269,769 @@ impl MockRouter {
                     headers
                 }),
                 body: r#"{"server":"nox","version":"0.1.0","handshake":"kick-nox-v1","capabilities":["mock","health","config"]}"#.to_string(),
-            },
-        });
+                ..Default::default()
+            })),
+        })
+        .unwrap();
     }
 
     pub fn from_config(config: &MockConfig) -> Self {
         let mut router = Self::new();
-        
+        router.max_body_size = config.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE);
+        router.default_delay = config.default_delay.clone();
+        router.journal = Journal::new(
+            config.journal_size.unwrap_or(DEFAULT_JOURNAL_SIZE),
+            config.journal_body_limit.unwrap_or(DEFAULT_JOURNAL_BODY_LIMIT),
+        );
+        router.verbose_not_found = config.verbose_not_found.unwrap_or(true);
+        router.set_passthrough(config);
+
         for scenario in &config.scenarios {
+            if let Err(err) = router.add_scenario(scenario) {
+                eprintln!("Skipping scenario {}: {}", scenario.name, err);
+                continue;
+            }
             for route in &scenario.routes {
-                router.add_route(route);
+                router.add_route(Some(&scenario.name), route, RouteSource::Config);
             }
         }
         
         router
     }
 
-    fn add_route(&mut self, route: &MockRoute) {
-        if let Ok(method) = route.method.parse::<Method>() {
-            self.routes.push(RouteMatcher {
-                path_pattern: route.path.clone(),
-                method,
-                response: route.response.clone(),
-            });
+    // Where unmatched requests go instead of a 404: recording takes priority
+    // over a plain fallback upstream
+    #[cfg(feature = "proxy")]
+    fn set_passthrough(&mut self, config: &MockConfig) {
+        self.fallback = config.fallback_upstream.as_deref().map(Passthrough::new);
+
+        let Some(record) = &config.record else {
+            return;
+        };
+        match Recorder::new(record) {
+            Ok(recorder) => {
+                println!("Recording unmatched requests from {} to {}", record.upstream, record.output.display());
+                self.recorder = Some(recorder);
+            }
+            Err(err) => eprintln!("Recording disabled: {}", err),
+        }
+    }
+
+    pub fn add_static_files(&mut self, handler: StaticFileHandler) {
+        // A mount at the root serves its own index page instead of the placeholder
+        if handler.prefix().is_empty() {
+            self.routes
+                .get_mut()
+                .unwrap()
+                .retain(|route| !(route.source == RouteSource::Builtin && route.path_pattern.as_str() == "/"));
+        }
+        self.static_files.push(handler);
+        self.static_files.sort_by_key(|h| std::cmp::Reverse(h.prefix().len()));
+    }
+
+    #[cfg(feature = "proxy")]
+    pub fn set_proxy(&mut self, proxy: ProxyHandler) {
+        self.proxy = Some(proxy);
+    }
+
+    #[cfg(feature = "proxy")]
+    pub fn proxy(&self) -> Option<&ProxyHandler> {
+        self.proxy.as_ref()
+    }
+
+    #[cfg(not(feature = "proxy"))]
+    fn set_passthrough(&mut self, config: &MockConfig) {
+        if config.record.is_some() {
+            eprintln!("Warning: 'record' requires the proxy feature, ignoring it");
+        }
+        if config.fallback_upstream.is_some() {
+            eprintln!("Warning: 'fallback_upstream' requires the proxy feature, ignoring it");
+        }
+    }
+
+    // Reseeds the generator behind weighted responses and latency sampling so
+    // a run can be replayed exactly
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+    }
+
+    fn add_scenario(&mut self, config: &MockScenario) -> std::result::Result<(), String> {
+        let scenarios = self.scenarios.get_mut().unwrap();
+        if scenarios.iter().any(|s| s.name == config.name) {
+            return Err("a scenario with this name already exists".to_string());
+        }
+
+        let mut enabled = config.enabled.unwrap_or(true);
+        if let Some(group) = &config.group {
+            let taken = scenarios
+                .iter()
+                .any(|s| s.enabled && s.group.as_ref() == Some(group));
+            if enabled && taken {
+                eprintln!(
+                    "Disabling scenario {}: another scenario in group {} is already enabled",
+                    config.name, group
+                );
+                enabled = false;
+            }
+        }
+
+        let initial_state = config
+            .initial_state
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SCENARIO_STATE.to_string());
+        scenarios.push(Scenario {
+            name: config.name.clone(),
+            group: config.group.clone(),
+            enabled,
+            state: initial_state.clone(),
+            initial_state,
+            hosts: config.hosts.clone().unwrap_or_default(),
+            listeners: config.listeners.clone().unwrap_or_default(),
+        });
+        Ok(())
+    }
+
+    fn add_route(&self, scenario: Option<&str>, route: &MockRoute, source: RouteSource) {
+        if route.scenario.is_some() {
+            eprintln!(
+                "Warning: 'scenario' on route {} {} only applies to runtime routes, ignoring it",
+                route.method, route.path
+            );
+        }
+        let result = Self::compile_route(route).and_then(|mut matcher| {
+            matcher.scenario = scenario.map(str::to_string);
+            matcher.source = source;
+            self.insert_route(matcher)
+        });
+        if let Err(err) = result {
+            eprintln!("Skipping route {} {}: {}", route.method, route.path, err);
+        }
+    }
+
+    // Adds a route to the live router; it takes effect on the next request.
+    // The route can join a scenario from config by naming it in `scenario`
+    pub fn add_runtime_route(&self, route: &MockRoute) -> std::result::Result<RouteInfo, String> {
+        let mut matcher = Self::compile_route(route)?;
+        match &route.scenario {
+            Some(name) if !self.scenarios.lock().unwrap().iter().any(|s| &s.name == name) => {
+                return Err(format!("unknown scenario '{}'", name));
+            }
+            None if route.required_state.is_some() => {
+                return Err("required_state needs a scenario to track the state".to_string());
+            }
+            _ => {}
+        }
+        matcher.scenario = route.scenario.clone();
+        matcher.source = RouteSource::Runtime;
+        self.insert_route(matcher)
+    }
+
+    fn insert_route(&self, mut matcher: RouteMatcher) -> std::result::Result<RouteInfo, String> {
+        let mut routes = self.routes.write().unwrap();
+        if matcher.id.is_empty() {
+            // Skip numbers a route with an explicit id has already taken
+            matcher.id = loop {
+                let id = format!("route-{}", self.next_route_id.fetch_add(1, Ordering::Relaxed));
+                if !routes.iter().any(|r| r.id == id) {
+                    break id;
+                }
+            };
+        } else if routes.iter().any(|r| r.id == matcher.id) {
+            return Err(format!("a route with id '{}' already exists", matcher.id));
+        }
+
+        let info = matcher.info();
+        routes.push(matcher);
+        // Keep routes ordered by specificity, with runtime stubs ahead of equally
+        // specific routes from config. The sort is stable so the rest keep their
+        // declaration order
+        routes.sort_by(|a, b| {
+            a.path_pattern
+                .precedence(&b.path_pattern)
+                .then_with(|| (b.source == RouteSource::Runtime).cmp(&(a.source == RouteSource::Runtime)))
+        });
+        Ok(info)
+    }
+
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.routes.read().unwrap().iter().map(RouteMatcher::info).collect()
+    }
+
+    pub fn remove_route(&self, id: &str) -> bool {
+        let mut routes = self.routes.write().unwrap();
+        let before = routes.len();
+        routes.retain(|r| r.id != id);
+        routes.len() != before
+    }
+
+    // Drops every route added at runtime and returns how many were removed
+    pub fn clear_runtime_routes(&self) -> usize {
+        let mut routes = self.routes.write().unwrap();
+        let before = routes.len();
+        routes.retain(|r| r.source != RouteSource::Runtime);
+        before - routes.len()
+    }
+
+    fn compile_route(route: &MockRoute) -> std::result::Result<RouteMatcher, String> {
+        let method = route
+            .method
+            .parse::<Method>()
+            .map_err(|_| format!("invalid method '{}'", route.method))?;
+
+        let responses = match (&route.response, &route.responses, &route.sequence) {
+            (Some(response), None, None) => Responses::Single(Arc::new(Self::compile_response(response)?)),
+            (None, Some(weighted), None) if !weighted.is_empty() => {
+                let weights = WeightedIndex::new(weighted.iter().map(|w| w.weight))
+                    .map_err(|e| format!("invalid response weights: {}", e))?;
+                let responses = weighted
+                    .iter()
+                    .map(|w| Self::compile_response(&w.response).map(Arc::new))
+                    .collect::<std::result::Result<_, _>>()?;
+                Responses::Weighted { responses, weights }
+            }
+            (None, None, Some(sequence)) if !sequence.is_empty() => Responses::Sequence {
+                responses: sequence
+                    .iter()
+                    .map(|r| Self::compile_response(r).map(Arc::new))
+                    .collect::<std::result::Result<_, _>>()?,
+                mode: route.sequence_mode.unwrap_or_default(),
+                calls: Arc::new(AtomicUsize::new(0)),
+                exhausted: Arc::new(MockResponse {
+                    status: StatusCode::NOT_FOUND.as_u16(),
+                    body: "Not Found".to_string(),
+                    ..Default::default()
+                }),
+            },
+            _ => return Err("exactly one of response, responses or sequence must be set".to_string()),
+        };
+
+        Ok(RouteMatcher {
+            id: route.id.clone().unwrap_or_default(),
+            source: RouteSource::Config,
+            path_pattern: PathPattern::parse(&route.path)?,
+            method,
+            request: RequestMatcher::compile(route)?,
+            responses,
+            scenario: None,
+            required_state: route.required_state.clone(),
+            definition: Some(route.clone()),
+        })
+    }
+
+    fn compile_response(response: &MockResponse) -> std::result::Result<MockResponse, String> {
+        let mut response = response.clone();
+        let sources = [!response.body.is_empty(), response.body_file.is_some(), response.body_json.is_some()];
+        if sources.iter().filter(|set| **set).count() > 1 {
+            return Err("only one of body, body_file and body_json may be set".to_string());
         }
+
+        // Structured bodies are serialized once here rather than per request
+        if let Some(json) = &response.body_json {
+            response.body = serde_json::to_string(json).map_err(|e| e.to_string())?;
+        }
+
+        if response.template.unwrap_or(false) {
+            Self::check_template(&response)?;
+        }
+
+        Ok(response)
+    }
+
+    #[cfg(feature = "templates")]
+    fn check_template(response: &MockResponse) -> std::result::Result<(), String> {
+        TemplateEngine::validate(&response.body)?;
+        for value in response.headers.iter().flat_map(|h| h.values()) {
+            TemplateEngine::validate(value)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "templates"))]
+    fn check_template(_response: &MockResponse) -> std::result::Result<(), String> {
+        eprintln!("Warning: 'template: true' requires the templates feature, serving body verbatim");
+        Ok(())
     }
 
-    pub async fn handle_request(&self, req: Request<Incoming>) -> std::result::Result<Response<Full<Bytes>>, Infallible> {
-        let path = req.uri().path();
-        let method = req.method();
+    pub async fn handle_request(&self, req: Request<Incoming>) -> std::result::Result<Response<NoxBody>, Infallible> {
+        let (ctx, overflow) = match RequestContext::from_request(req, self.max_body_size).await {
+            Ok(request) => request,
+            Err(err) => return Ok(self.create_body_error_response(&err)),
+        };
 
-        // Try to match routes
-        for route in &self.routes {
-            if self.matches_route(&route, path, method) {
-                return Ok(self.create_response(&route.response));
+        if admin::is_admin_path(ctx.path()) {
+            if overflow.is_some() {
+                return Ok(self.create_body_error_response(&BodyError::TooLarge(self.max_body_size)));
             }
+            return Ok(admin::handle(self, &ctx));
         }
 
-        // Default fallback
-        Ok(self.create_not_found_response())
+        // Body rules can't be judged on part of a body, so an oversized one
+        // skips the mocks and can only be passed on to an upstream
+        let route_match = match overflow {
+            Some(_) => None,
+            None => self.match_route(&ctx),
+        };
+        self.journal.record(&ctx, route_match.as_ref().map(|m| m.id.as_str()));
+
+        match route_match {
+            Some(route_match) => {
+                let response = self.create_response(&route_match, &ctx).await;
+                let delay = self.response_delay(&route_match.response);
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                if route_match.response.fault == Some(Fault::Hang) {
+                    std::future::pending::<()>().await;
+                }
+                Ok(response)
+            }
+            None => Ok(self.handle_unmatched(&ctx, overflow).await),
+        }
     }
 
-    fn matches_route(&self, route: &RouteMatcher, path: &str, method: &Method) -> bool {
-        if route.method != *method {
-            return false;
+    // Mocks take priority; only requests no route matched are served from
+    // static files, proxied or recorded. A body over the size limit can only
+    // be streamed on to an upstream; anywhere else it is refused with a 413
+    async fn handle_unmatched(&self, ctx: &RequestContext, overflow: Option<Overflow>) -> Response<NoxBody> {
+        for handler in self.static_files.iter().filter(|_| overflow.is_none()) {
+            if let Some(response) = handler.handle(ctx).await {
+                return response;
+            }
+        }
+
+        #[cfg(feature = "proxy")]
+        let mut overflow = overflow;
+        #[cfg(feature = "proxy")]
+        {
+            if let Some(proxy) = &self.proxy {
+                if let Some(response) = proxy.handle(ctx, &mut overflow).await {
+                    return response;
+                }
+            }
+            // Recordings hold the whole body, so the recorder needs it buffered
+            if let Some(recorder) = self.recorder.as_ref().filter(|_| overflow.is_none()) {
+                return recorder.forward(ctx).await;
+            }
+            if let Some(fallback) = &self.fallback {
+                return fallback.forward(ctx, overflow.take()).await;
+            }
+        }
+
+        // Last, so an SPA mount at / doesn't swallow proxied paths like /api/users
+        for handler in self.static_files.iter().filter(|_| overflow.is_none()) {
+            if let Some(response) = handler.handle_spa_fallback(ctx).await {
+                return response;
+            }
         }
 
-        // Simple exact match for now - can be enhanced with path parameters
-        route.path_pattern == path
+        if overflow.is_some() {
+            self.create_body_error_response(&BodyError::TooLarge(self.max_body_size))
+        } else if self.verbose_not_found {
+            self.create_unmatched_response(ctx)
+        } else {
+            self.create_not_found_response()
+        }
     }
 
-    fn create_response(&self, mock_response: &MockResponse) -> Response<Full<Bytes>> {
+    pub fn match_route(&self, ctx: &RequestContext) -> Option<RouteMatch> {
+        let routes = self.routes.read().unwrap();
+
+        routes
+            .iter()
+            .filter(|route| route.method == ctx.method)
+            .find_map(|route| {
+                let params = route.path_pattern.matches(ctx.path())?;
+                if !route.request.matches(ctx) {
+                    return None;
+                }
+
+                let mut scenarios = self.scenarios.lock().unwrap();
+                // Routes outside any scenario (the built-in ones) are always active
+                let mut scenario = match &route.scenario {
+                    Some(name) => Some(scenarios.iter_mut().find(|s| &s.name == name)?),
+                    None => None,
+                };
+                if scenario.as_ref().is_some_and(|s| !s.enabled || s.mismatch(ctx).is_some()) {
+                    return None;
+                }
+                if let Some(required) = &route.required_state {
+                    if scenario.as_ref().is_none_or(|s| &s.state != required) {
+                        return None;
+                    }
+                }
+
+                let response = self.select_response(&route.responses);
+                if let (Some(scenario), Some(new_state)) = (scenario.as_mut(), &response.new_state) {
+                    scenario.state = new_state.clone();
+                }
+
+                Some(RouteMatch {
+                    id: route.id.clone(),
+                    pattern: route.path_pattern.as_str().to_string(),
+                    params,
+                    response: Arc::clone(response),
+                })
+            })
+    }
+
+    // The routes that came closest to matching, for explaining a 404. Routes
+    // that got further through matching rank first; among routes whose path
+    // didn't match, the most similar pattern wins
+    pub fn candidates(&self, ctx: &RequestContext) -> Vec<Candidate> {
+        let routes = self.routes.read().unwrap();
+        // A copy, so the lock isn't held while the request matchers run
+        let scenarios = self.scenarios.lock().unwrap().clone();
+
+        let mut candidates: Vec<Candidate> = routes
+            .iter()
+            .filter_map(|route| {
+                let scenario = route
+                    .scenario
+                    .as_ref()
+                    .and_then(|name| scenarios.iter().find(|s| &s.name == name));
+
+                let (stage, reason) = if route.path_pattern.matches(ctx.path()).is_none() {
+                    (Stage::Path, "path does not match".to_string())
+                } else if route.method != ctx.method {
+                    (Stage::Method, format!("method is {}, route expects {}", ctx.method, route.method))
+                } else if let Some(scenario) = scenario.filter(|s| !s.enabled) {
+                    (Stage::Scenario, format!("scenario '{}' is disabled", scenario.name))
+                } else if let Some(reason) = scenario.and_then(|s| s.mismatch(ctx)) {
+                    (Stage::Scenario, reason)
+                } else if let Some(required) = &route.required_state {
+                    let current = scenario.map_or("", |s| s.state.as_str());
+                    if current == required {
+                        (Stage::Request, route.request.mismatch(ctx)?.to_string())
+                    } else {
+                        (
+                            Stage::State,
+                            format!("scenario state is '{}', route requires '{}'", current, required),
+                        )
+                    }
+                } else {
+                    (Stage::Request, route.request.mismatch(ctx)?.to_string())
+                };
+
+                let distance = match stage {
+                    Stage::Path => path_distance(ctx.path(), route.path_pattern.as_str()),
+                    _ => 0,
+                };
+                Some(Candidate {
+                    id: route.id.clone(),
+                    method: route.method.to_string(),
+                    path: route.path_pattern.as_str().to_string(),
+                    scenario: route.scenario.clone(),
+                    reason,
+                    stage,
+                    distance,
+                })
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| b.stage.cmp(&a.stage).then(a.distance.cmp(&b.distance)));
+        candidates.truncate(MAX_CANDIDATES);
+        candidates
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn scenarios(&self) -> Vec<ScenarioStatus> {
+        self.scenarios
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| ScenarioStatus {
+                name: s.name.clone(),
+                group: s.group.clone(),
+                enabled: s.enabled,
+                state: s.state.clone(),
+                initial_state: s.initial_state.clone(),
+                hosts: s.hosts.clone(),
+                listeners: s.listeners.clone(),
+            })
+            .collect()
+    }
+
+    pub fn scenario(&self, name: &str) -> Option<ScenarioStatus> {
+        self.scenarios().into_iter().find(|s| s.name == name)
+    }
+
+    pub fn set_scenario_state(&self, name: &str, state: &str) -> std::result::Result<(), ScenarioError> {
+        let mut scenarios = self.scenarios.lock().unwrap();
+        let scenario = scenarios
+            .iter_mut()
+            .find(|s| s.name == name)
+            .ok_or(ScenarioError::UnknownScenario)?;
+        scenario.state = state.to_string();
+        Ok(())
+    }
+
+    // Puts one scenario, or every scenario, back into its initial state
+    pub fn reset_scenario_states(&self, name: Option<&str>) -> std::result::Result<(), ScenarioError> {
+        let mut scenarios = self.scenarios.lock().unwrap();
+        let mut found = name.is_none();
+        for scenario in scenarios.iter_mut() {
+            if name.is_none_or(|name| name == scenario.name) {
+                scenario.state = scenario.initial_state.clone();
+                found = true;
+            }
+        }
+        if found { Ok(()) } else { Err(ScenarioError::UnknownScenario) }
+    }
+
+    // Enabling a grouped scenario disables the rest of its group
+    pub fn set_scenario_enabled(&self, name: &str, enabled: bool) -> std::result::Result<(), ScenarioError> {
+        let mut scenarios = self.scenarios.lock().unwrap();
+        let group = scenarios
+            .iter()
+            .find(|s| s.name == name)
+            .ok_or(ScenarioError::UnknownScenario)?
+            .group
+            .clone();
+
+        for scenario in scenarios.iter_mut() {
+            if scenario.name == name {
+                scenario.enabled = enabled;
+            } else if enabled && group.is_some() && scenario.group == group {
+                scenario.enabled = false;
+            }
+        }
+        Ok(())
+    }
+
+    // Switches a group over to one of its scenarios in a single step
+    pub fn activate_scenario(&self, group: &str, name: &str) -> std::result::Result<(), ScenarioError> {
+        let mut scenarios = self.scenarios.lock().unwrap();
+        if !scenarios.iter().any(|s| s.group.as_deref() == Some(group)) {
+            return Err(ScenarioError::UnknownGroup);
+        }
+        let scenario = scenarios
+            .iter()
+            .find(|s| s.name == name)
+            .ok_or(ScenarioError::UnknownScenario)?;
+        if scenario.group.as_deref() != Some(group) {
+            return Err(ScenarioError::NotInGroup);
+        }
+
+        for scenario in scenarios.iter_mut() {
+            if scenario.group.as_deref() == Some(group) {
+                scenario.enabled = scenario.name == name;
+            }
+        }
+        Ok(())
+    }
+
+    fn select_response<'a>(&self, responses: &'a Responses) -> &'a Arc<MockResponse> {
+        match responses {
+            Responses::Single(response) => response,
+            Responses::Weighted { responses, weights } => {
+                let index = weights.sample(&mut *self.rng.lock().unwrap());
+                &responses[index]
+            }
+            Responses::Sequence { responses, mode, calls, exhausted } => {
+                let call = calls.fetch_add(1, Ordering::Relaxed);
+                match mode {
+                    SequenceMode::StopAtLast => &responses[call.min(responses.len() - 1)],
+                    SequenceMode::Cycle => &responses[call % responses.len()],
+                    SequenceMode::Then404 => responses.get(call).unwrap_or(exhausted),
+                }
+            }
+        }
+    }
+
+    // Rewinds the response sequences of one scenario's routes, or of every
+    // route, to their first response
+    pub fn reset_sequences(&self, scenario: Option<&str>) {
+        let routes = self.routes.read().unwrap();
+        let in_scope = routes
+            .iter()
+            .filter(|route| scenario.is_none() || route.scenario.as_deref() == scenario);
+        for route in in_scope {
+            if let Responses::Sequence { calls, .. } = &route.responses {
+                calls.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+
+    async fn create_response(&self, route_match: &RouteMatch, ctx: &RequestContext) -> Response<NoxBody> {
+        match self.build_response(route_match, ctx).await {
+            Ok(response) => response,
+            Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                self.create_not_found_response()
+            }
+            Err(err) => self.create_error_response(&err),
+        }
+    }
+
+    async fn build_response(&self, route_match: &RouteMatch, ctx: &RequestContext) -> crate::Result<Response<NoxBody>> {
+        let mock_response = &route_match.response;
+        let (body, inferred_type) = self.load_body(route_match).await?;
+        let (headers, body) = self.render(route_match, ctx, body)?;
+
         let mut builder = Response::builder()
             .status(StatusCode::from_u16(mock_response.status).unwrap_or(StatusCode::OK));
 
+        // Only infer a content type when the route doesn't set one itself
+        let has_content_type = headers.keys().any(|key| key.eq_ignore_ascii_case("content-type"));
+        if let (false, Some(content_type)) = (has_content_type, inferred_type) {
+            builder = builder.header(CONTENT_TYPE, content_type);
+        }
+
         // Add headers if configured
-        if let Some(headers) = &mock_response.headers {
-            for (key, value) in headers {
-                builder = builder.header(key, value);
+        for (key, value) in headers {
+            builder = builder.header(key, value);
+        }
+
+        if let Some(fault) = mock_response.fault {
+            builder = builder.extension(fault);
+        }
+
+        let body = match (mock_response.fault, mock_response.bandwidth) {
+            (Some(Fault::TruncatedBody), _) => {
+                builder = with_content_length(builder, body.len());
+                fault::truncated(body)
+            }
+            (Some(Fault::ConnectionReset), _) => {
+                builder = with_content_length(builder, body.len());
+                fault::interrupted(body)
+            }
+            (_, Some(bytes_per_second)) if bytes_per_second > 0 => {
+                builder = with_content_length(builder, body.len());
+                body::throttled(body, bytes_per_second)
             }
+            _ => body::full(body),
+        };
+
+        Ok(builder.body(body)?)
+    }
+
+    fn response_delay(&self, mock_response: &MockResponse) -> Duration {
+        let mut rng = self.rng.lock().unwrap();
+        let mut delay = mock_response
+            .delay
+            .as_ref()
+            .or(self.default_delay.as_ref())
+            .map(|spec| spec.sample(&mut *rng))
+            .unwrap_or_default();
+
+        if let Some(jitter) = mock_response.jitter.filter(|j| *j > 0) {
+            delay += Duration::from_millis(rng.gen_range(0..=jitter));
         }
+        delay
+    }
 
-        builder
-            .body(Full::new(Bytes::from(mock_response.body.clone())))
+    // Returns the raw response body along with a content type inferred from its source
+    async fn load_body(&self, route_match: &RouteMatch) -> crate::Result<(Bytes, Option<String>)> {
+        let mock_response = &route_match.response;
+
+        if let Some(body_file) = &mock_response.body_file {
+            let path = resolve_body_file(body_file, &route_match.params)?;
+            let content_type = mime_guess::from_path(&path).first_or_octet_stream().to_string();
+            // Templated paths are read every time; caching them would keep one
+            // entry per distinct parameter value for the life of the process
+            let cacheable = path.as_path() == body_file.as_path();
+            return Ok((self.read_body_file(path, cacheable).await?, Some(content_type)));
+        }
+
+        let content_type = mock_response
+            .body_json
+            .as_ref()
+            .map(|_| mime::APPLICATION_JSON.to_string());
+        Ok((Bytes::from(mock_response.body.clone()), content_type))
+    }
+
+    async fn read_body_file(&self, path: PathBuf, cacheable: bool) -> crate::Result<Bytes> {
+        if let Some(cached) = self.file_cache.lock().unwrap().get(&path) {
+            return Ok(cached.clone());
+        }
+
+        let contents = Bytes::from(tokio::fs::read(&path).await?);
+        if !cacheable {
+            return Ok(contents);
+        }
+        self.file_cache
+            .lock()
             .unwrap()
+            .insert(path, contents.clone());
+        Ok(contents)
     }
 
-    fn create_not_found_response(&self) -> Response<Full<Bytes>> {
+    #[cfg(feature = "templates")]
+    fn render(&self, route_match: &RouteMatch, ctx: &RequestContext, body: Bytes) -> crate::Result<(HashMap<String, String>, Bytes)> {
+        let mock_response = &route_match.response;
+        let headers = mock_response.headers.clone().unwrap_or_default();
+
+        if !mock_response.template.unwrap_or(false) {
+            return Ok((headers, body));
+        }
+
+        let data = TemplateEngine::context_for(ctx, &route_match.params);
+        let headers = headers
+            .into_iter()
+            .map(|(key, value)| Ok((key, self.templates.render_string(&value, &data)?)))
+            .collect::<crate::Result<_>>()?;
+        let body = self
+            .templates
+            .render_string(&String::from_utf8_lossy(&body), &data)?;
+
+        Ok((headers, Bytes::from(body)))
+    }
+
+    #[cfg(not(feature = "templates"))]
+    fn render(&self, route_match: &RouteMatch, _ctx: &RequestContext, body: Bytes) -> crate::Result<(HashMap<String, String>, Bytes)> {
+        Ok((route_match.response.headers.clone().unwrap_or_default(), body))
+    }
+
+    fn create_error_response(&self, err: &dyn Display) -> Response<NoxBody> {
+        eprintln!("Error building mock response: {}", err);
+        Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(body::full(format!("Mock response error: {}", err)))
+            .unwrap()
+    }
+
+    fn create_body_error_response(&self, err: &BodyError) -> Response<NoxBody> {
+        let status = match err {
+            BodyError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            BodyError::Read(_) => StatusCode::BAD_REQUEST,
+        };
+
+        Response::builder()
+            .status(status)
+            .body(body::full(err.to_string()))
+            .unwrap()
+    }
+
+    fn create_unmatched_response(&self, ctx: &RequestContext) -> Response<NoxBody> {
+        let candidates = self.candidates(ctx);
+
+        eprintln!("No route matched {} {}", ctx.method, ctx.path());
+        for candidate in &candidates {
+            eprintln!(
+                "  closest: {} {} [{}]: {}",
+                candidate.method, candidate.path, candidate.id, candidate.reason
+            );
+        }
+
+        let body = serde_json::json!({
+            "error": "No route matched",
+            "method": ctx.method.as_str(),
+            "path": ctx.path(),
+            "candidates": candidates,
+        });
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body::full(body.to_string()))
+            .unwrap()
+    }
+
+    fn create_not_found_response(&self) -> Response<NoxBody> {
         Response::builder()
             .status(StatusCode::NOT_FOUND)
-            .body(Full::new(Bytes::from("Not Found")))
+            .body(body::full("Not Found"))
             .unwrap()
     }
 }
@@ -148,4 +1040,449 @@ impl Default for MockRouter {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+impl RouteMatcher {
+    fn info(&self) -> RouteInfo {
+        RouteInfo {
+            id: self.id.clone(),
+            method: self.method.to_string(),
+            path: self.path_pattern.as_str().to_string(),
+            source: self.source,
+            scenario: self.scenario.clone(),
+            definition: self.definition.clone(),
+        }
+    }
+}
+
+impl Scenario {
+    // Why a request is outside the hosts and listeners this scenario serves,
+    // or None if it is served
+    fn mismatch(&self, ctx: &RequestContext) -> Option<String> {
+        if !self.hosts.is_empty() {
+            let host = ctx.host().unwrap_or_default();
+            if !self.hosts.iter().any(|pattern| host_matches(pattern, host)) {
+                return Some(format!(
+                    "scenario '{}' serves hosts {}, not '{}'",
+                    self.name,
+                    self.hosts.join(", "),
+                    host
+                ));
+            }
+        }
+        if !self.listeners.is_empty() {
+            let listener = ctx.listener.as_deref().unwrap_or_default();
+            if !self.listeners.iter().any(|name| name == listener) {
+                return Some(format!(
+                    "scenario '{}' serves listeners {}, not '{}'",
+                    self.name,
+                    self.listeners.join(", "),
+                    listener
+                ));
+            }
+        }
+        None
+    }
+}
+
+// Host names are case-insensitive; "*.example.com" matches any subdomain but
+// not example.com itself
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.len() > domain.len() + 1
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+            && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+// How different a path is from a pattern, treating placeholders as matching
+// whatever segment is in their place
+fn path_distance(path: &str, pattern: &str) -> usize {
+    let segments: Vec<&str> = path.split('/').collect();
+    let filled: Vec<&str> = pattern
+        .split('/')
+        .enumerate()
+        .map(|(i, part)| match segments.get(i) {
+            Some(segment) if part.starts_with('{') || part.starts_with('*') => *segment,
+            _ => part,
+        })
+        .collect();
+    edit_distance(path, &filled.join("/"))
+}
+
+// Levenshtein distance, used to rank near-miss paths
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Streamed bodies have no size hint, so keep the length explicit unless the
+// route already set one
+fn with_content_length(builder: http::response::Builder, len: usize) -> http::response::Builder {
+    if builder.headers_ref().is_some_and(|h| h.contains_key(CONTENT_LENGTH)) {
+        builder
+    } else {
+        builder.header(CONTENT_LENGTH, len)
+    }
+}
+
+// Substitutes {param} placeholders in a body_file path, refusing values that
+// would escape the configured directory
+fn resolve_body_file(body_file: &std::path::Path, params: &PathParams) -> crate::Result<PathBuf> {
+    let mut path = body_file.to_string_lossy().into_owned();
+    if !path.contains('{') {
+        return Ok(body_file.to_path_buf());
+    }
+
+    for (name, value) in params {
+        let escapes = PathBuf::from(value)
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)));
+        if escapes {
+            return Err(Error::Io(std::io::ErrorKind::NotFound.into()));
+        }
+        path = path.replace(&format!("{{{}}}", name), value);
+    }
+
+    Ok(PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderMap;
+
+    fn router(yaml: &str) -> MockRouter {
+        MockRouter::from_config(&serde_yaml::from_str(yaml).unwrap())
+    }
+
+    fn request(method: &str, path: &str) -> RequestContext {
+        RequestContext::new(method.parse().unwrap(), path.parse().unwrap(), HeaderMap::new(), Bytes::new())
+    }
+
+    fn status(router: &MockRouter, method: &str, path: &str) -> Option<u16> {
+        router.match_route(&request(method, path)).map(|m| m.response.status)
+    }
+
+    fn sequence_router(mode: &str) -> MockRouter {
+        router(&format!(
+            r#"
+scenarios:
+  - name: jobs
+    routes:
+      - path: /job
+        method: GET
+        sequence_mode: {}
+        sequence: [{{ status: 201 }}, {{ status: 202 }}, {{ status: 203 }}]
+"#,
+            mode
+        ))
+    }
+
+    fn statuses(router: &MockRouter, calls: usize) -> Vec<Option<u16>> {
+        (0..calls).map(|_| status(router, "GET", "/job")).collect()
+    }
+
+    #[test]
+    fn test_sequence_stop_at_last() {
+        let router = sequence_router("stop-at-last");
+        assert_eq!(statuses(&router, 5), [Some(201), Some(202), Some(203), Some(203), Some(203)]);
+    }
+
+    #[test]
+    fn test_sequence_cycle() {
+        let router = sequence_router("cycle");
+        assert_eq!(statuses(&router, 5), [Some(201), Some(202), Some(203), Some(201), Some(202)]);
+    }
+
+    #[test]
+    fn test_sequence_then_404() {
+        let router = sequence_router("then-404");
+        assert_eq!(statuses(&router, 5), [Some(201), Some(202), Some(203), Some(404), Some(404)]);
+    }
+
+    #[test]
+    fn test_sequence_count_is_shared_by_clones() {
+        let router = sequence_router("stop-at-last");
+        let responses = {
+            let routes = router.routes.read().unwrap();
+            routes.iter().find(|r| r.path_pattern.as_str() == "/job").unwrap().responses.clone()
+        };
+
+        assert_eq!(router.select_response(&responses).status, 201);
+        assert_eq!(status(&router, "GET", "/job"), Some(202));
+        assert_eq!(router.select_response(&responses).status, 203);
+    }
+
+    #[test]
+    fn test_reset_sequences_by_scenario() {
+        let router = sequence_router("stop-at-last");
+        statuses(&router, 2);
+
+        router.reset_sequences(Some("other"));
+        assert_eq!(status(&router, "GET", "/job"), Some(203));
+        router.reset_sequences(Some("jobs"));
+        assert_eq!(status(&router, "GET", "/job"), Some(201));
+        router.reset_sequences(None);
+        assert_eq!(status(&router, "GET", "/job"), Some(201));
+    }
+
+    fn scenario_state(router: &MockRouter, name: &str) -> String {
+        router.scenario(name).unwrap().state
+    }
+
+    #[test]
+    fn test_scenario_state_machine() {
+        let router = router(
+            r#"
+scenarios:
+  - name: shop
+    initial_state: LoggedOut
+    routes:
+      - { path: /login, method: POST, required_state: LoggedOut, response: { status: 200, new_state: LoggedIn } }
+      - { path: /cart, method: POST, required_state: LoggedIn, response: { status: 201, new_state: HasItems } }
+      - { path: /checkout, method: POST, required_state: HasItems, response: { status: 202, new_state: Done } }
+"#,
+        );
+        assert_eq!(scenario_state(&router, "shop"), "LoggedOut");
+
+        // Routes outside their required state don't match, and leave the state alone
+        assert_eq!(status(&router, "POST", "/checkout"), None);
+        assert_eq!(status(&router, "POST", "/cart"), None);
+        assert_eq!(scenario_state(&router, "shop"), "LoggedOut");
+
+        assert_eq!(status(&router, "POST", "/login"), Some(200));
+        assert_eq!(scenario_state(&router, "shop"), "LoggedIn");
+        assert_eq!(status(&router, "POST", "/checkout"), None);
+        assert_eq!(status(&router, "POST", "/cart"), Some(201));
+        assert_eq!(scenario_state(&router, "shop"), "HasItems");
+        assert_eq!(status(&router, "POST", "/checkout"), Some(202));
+        assert_eq!(scenario_state(&router, "shop"), "Done");
+        assert_eq!(status(&router, "POST", "/login"), None);
+
+        router.reset_scenario_states(Some("shop")).unwrap();
+        assert_eq!(scenario_state(&router, "shop"), "LoggedOut");
+        assert_eq!(status(&router, "POST", "/login"), Some(200));
+    }
+
+    #[test]
+    fn test_set_and_reset_scenario_state() {
+        let router = router(
+            r#"
+scenarios:
+  - name: a
+    routes: []
+  - name: b
+    initial_state: Ready
+    routes: []
+"#,
+        );
+        assert_eq!(scenario_state(&router, "a"), DEFAULT_SCENARIO_STATE);
+
+        router.set_scenario_state("a", "X").unwrap();
+        router.set_scenario_state("b", "Y").unwrap();
+        assert_eq!(router.set_scenario_state("c", "Z"), Err(ScenarioError::UnknownScenario));
+        assert_eq!(router.reset_scenario_states(Some("c")), Err(ScenarioError::UnknownScenario));
+
+        router.reset_scenario_states(None).unwrap();
+        assert_eq!(scenario_state(&router, "a"), DEFAULT_SCENARIO_STATE);
+        assert_eq!(scenario_state(&router, "b"), "Ready");
+    }
+
+    fn enabled(router: &MockRouter) -> Vec<(String, bool)> {
+        router.scenarios().into_iter().map(|s| (s.name, s.enabled)).collect()
+    }
+
+    fn grouped_router() -> MockRouter {
+        router(
+            r#"
+scenarios:
+  - name: healthy
+    group: mode
+    routes:
+      - { path: /status, method: GET, response: { status: 200 } }
+  - name: outage
+    group: mode
+    routes:
+      - { path: /status, method: GET, response: { status: 503 } }
+  - name: extra
+    routes: []
+"#,
+        )
+    }
+
+    #[test]
+    fn test_group_allows_one_enabled_scenario_at_load() {
+        let router = grouped_router();
+        assert_eq!(
+            enabled(&router),
+            [("healthy".to_string(), true), ("outage".to_string(), false), ("extra".to_string(), true)]
+        );
+        assert_eq!(status(&router, "GET", "/status"), Some(200));
+    }
+
+    #[test]
+    fn test_enabling_disables_rest_of_group() {
+        let router = grouped_router();
+        router.set_scenario_enabled("outage", true).unwrap();
+        assert_eq!(
+            enabled(&router),
+            [("healthy".to_string(), false), ("outage".to_string(), true), ("extra".to_string(), true)]
+        );
+        assert_eq!(status(&router, "GET", "/status"), Some(503));
+
+        // Disabling leaves the group with nothing enabled
+        router.set_scenario_enabled("outage", false).unwrap();
+        assert_eq!(status(&router, "GET", "/status"), None);
+        assert_eq!(router.set_scenario_enabled("missing", true), Err(ScenarioError::UnknownScenario));
+    }
+
+    #[test]
+    fn test_activate_scenario() {
+        let router = grouped_router();
+        router.activate_scenario("mode", "outage").unwrap();
+        assert_eq!(status(&router, "GET", "/status"), Some(503));
+        router.activate_scenario("mode", "healthy").unwrap();
+        assert_eq!(status(&router, "GET", "/status"), Some(200));
+
+        assert_eq!(router.activate_scenario("other", "healthy"), Err(ScenarioError::UnknownGroup));
+        assert_eq!(router.activate_scenario("mode", "missing"), Err(ScenarioError::UnknownScenario));
+        assert_eq!(router.activate_scenario("mode", "extra"), Err(ScenarioError::NotInGroup));
+        assert_eq!(status(&router, "GET", "/status"), Some(200));
+    }
+
+    #[test]
+    fn test_duplicate_scenario_is_skipped_with_its_routes() {
+        let router = router(
+            r#"
+scenarios:
+  - name: dup
+    routes:
+      - { path: /first, method: GET, response: { status: 200 } }
+  - name: dup
+    enabled: false
+    routes:
+      - { path: /second, method: GET, response: { status: 200 } }
+"#,
+        );
+        assert_eq!(enabled(&router), [("dup".to_string(), true)]);
+        assert_eq!(status(&router, "GET", "/first"), Some(200));
+        assert_eq!(status(&router, "GET", "/second"), None);
+    }
+
+    fn runtime_route(id: Option<&str>, path: &str) -> MockRoute {
+        MockRoute {
+            id: id.map(str::to_string),
+            path: path.to_string(),
+            method: "GET".to_string(),
+            response: Some(MockResponse { status: 200, ..Default::default() }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_generated_ids_skip_explicit_ones() {
+        let router = MockRouter::new();
+        let next = router.next_route_id.load(Ordering::Relaxed);
+        let taken = format!("route-{}", next);
+
+        let explicit = runtime_route(Some(&taken), "/a");
+        assert_eq!(router.add_runtime_route(&explicit).unwrap().id, taken);
+        let generated = runtime_route(None, "/b");
+        assert_eq!(router.add_runtime_route(&generated).unwrap().id, format!("route-{}", next + 1));
+
+        assert!(router.add_runtime_route(&explicit).is_err());
+        let before = router.routes().len();
+        assert!(router.remove_route(&taken));
+        assert_eq!(router.routes().len(), before - 1);
+        assert!(!router.remove_route(&taken));
+    }
+
+    #[test]
+    fn test_clear_runtime_routes_keeps_config_routes() {
+        let router = router(
+            r#"
+scenarios:
+  - name: s
+    routes:
+      - { id: configured, path: /a, method: GET, response: { status: 200 } }
+"#,
+        );
+        let builtin = router.routes().len() - 1;
+        router.add_runtime_route(&runtime_route(None, "/a")).unwrap();
+        router.add_runtime_route(&runtime_route(None, "/b")).unwrap();
+
+        assert_eq!(router.clear_runtime_routes(), 2);
+        let routes = router.routes();
+        assert_eq!(routes.len(), builtin + 1);
+        assert!(routes.iter().any(|r| r.id == "configured"));
+        assert!(routes.iter().all(|r| r.source != RouteSource::Runtime));
+        assert_eq!(router.clear_runtime_routes(), 0);
+    }
+
+    #[test]
+    fn test_path_distance_ignores_placeholders() {
+        assert_eq!(path_distance("/users/42", "/users/{id}"), 0);
+        assert_eq!(path_distance("/users/42/posts", "/users/{id}/posts"), 0);
+        assert_eq!(path_distance("/user/42", "/users/{id}"), 1);
+        assert_eq!(path_distance("/files/a/b", "/files/*"), 2);
+        assert_eq!(path_distance("/orders", "/users/{id}"), edit_distance("/orders", "/users/{id}"));
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_candidates_rank_by_stage_then_distance() {
+        let router = router(
+            r#"
+scenarios:
+  - name: flow
+    routes:
+      - { id: typo, path: /order, method: GET, response: { status: 200 } }
+      - { id: far, path: /something/else, method: GET, response: { status: 200 } }
+      - { id: method, path: /orders, method: POST, response: { status: 200 } }
+      - { id: state, path: /orders, method: GET, required_state: Paid, response: { status: 200 } }
+"#,
+        );
+
+        let candidates = router.candidates(&request("GET", "/orders"));
+        let ids: Vec<&str> = candidates.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["state", "method", "typo"]);
+        assert_eq!(candidates[0].reason, "scenario state is 'Started', route requires 'Paid'");
+        assert_eq!(candidates[1].reason, "method is GET, route expects POST");
+        assert_eq!(candidates[2].reason, "path does not match");
+    }
+
+    #[test]
+    fn test_candidates_explain_disabled_scenarios_and_request_rules() {
+        let router = router(
+            r#"
+scenarios:
+  - name: off
+    enabled: false
+    routes:
+      - { id: disabled, path: /pay, method: POST, response: { status: 200 } }
+  - name: on
+    routes:
+      - { id: header, path: /pay, method: POST, headers: { x-key: secret }, response: { status: 200 } }
+"#,
+        );
+
+        let candidates = router.candidates(&request("POST", "/pay"));
+        assert_eq!(candidates[0].id, "header");
+        assert!(candidates[0].reason.contains("x-key"), "{}", candidates[0].reason);
+        assert_eq!(candidates[1].id, "disabled");
+        assert_eq!(candidates[1].reason, "scenario 'off' is disabled");
+    }
+}
@@ -1,66 +1,594 @@
-use hyper::server::conn::http1;
 use hyper::service::service_fn;
-use hyper_util::rt::TokioIo;
-use std::net::SocketAddr;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 use std::sync::Arc;
+use std::time::Duration;
 use crate::Result;
+use crate::context::ListenerName;
+use crate::error::Error;
+use crate::fault::{AbortiveClose, FaultHandle, FaultIo};
+use crate::harness::NoxHandle;
 use crate::router::MockRouter;
+use crate::shutdown::{self, ShutdownHandle};
+use crate::static_files::StaticFileHandler;
+
+#[cfg(feature = "proxy")]
+use crate::proxy::ProxyHandler;
 
 #[cfg(feature = "config")]
-use crate::config::NoxConfig;
+use crate::config::{ListenerConfig, NoxConfig};
+
+#[cfg(feature = "tls")]
+use crate::config::TlsConfig;
+
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 200;
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The name of the listener on server.host/port
+pub const DEFAULT_LISTENER: &str = "default";
 
 pub struct NoxServer {
-    addr: SocketAddr,
+    listeners: Vec<Listener>,
     router: Arc<MockRouter>,
+    http2: Http2Options,
+    shutdown: ShutdownHandle,
+    drain_timeout: Duration,
+    handle_signals: bool,
+    shutdown_hooks: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+// A socket the server accepts connections on. Requests carry the listener's
+// name, so scenarios can be limited to some listeners
+#[derive(Debug, Clone)]
+pub struct Listener {
+    pub name: String,
+    pub bind: Bind,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Bind {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+struct BoundListener {
+    name: Arc<str>,
+    socket: Socket,
+    // The address actually bound, which differs from the configured one for port 0
+    addr: Option<SocketAddr>,
+    scheme: &'static str,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+}
+
+enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+// Shared by every connection task
+struct Connections {
+    builder: auto::Builder<TokioExecutor>,
+    router: Arc<MockRouter>,
+    shutdown: ShutdownHandle,
+    // Cancelled once the drain timeout runs out, dropping what is left
+    abort: CancellationToken,
+    tracker: TaskTracker,
+}
+
+#[derive(Debug, Clone)]
+pub struct Http2Options {
+    // When false, connections are served as HTTP/1.1 only
+    pub enabled: bool,
+    pub max_concurrent_streams: u32,
+    // Pings are only sent when an interval is set
+    pub keep_alive_interval: Option<Duration>,
+    pub keep_alive_timeout: Duration,
+}
+
+impl Default for Http2Options {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_concurrent_streams: DEFAULT_MAX_CONCURRENT_STREAMS,
+            keep_alive_interval: None,
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+        }
+    }
 }
 
 impl NoxServer {
     pub fn new(addr: SocketAddr) -> Self {
         Self { 
-            addr,
+            listeners: vec![Listener::tcp(DEFAULT_LISTENER, addr)],
             router: Arc::new(MockRouter::new()),
+            http2: Http2Options::default(),
+            shutdown: ShutdownHandle::new(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            handle_signals: false,
+            shutdown_hooks: Vec::new(),
         }
     }
 
+    pub fn with_listener(mut self, listener: Listener) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    pub fn with_http2(mut self, http2: Http2Options) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    // How long in-flight requests get to finish once shutdown starts
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    // Shuts down gracefully on SIGINT or SIGTERM. Off by default, so an
+    // embedding process keeps its own signal handling
+    pub fn with_signal_shutdown(mut self) -> Self {
+        self.handle_signals = true;
+        self
+    }
+
+    // Runs once connections have drained, before `run` returns, in the order
+    // the hooks were added
+    pub fn on_shutdown(mut self, hook: impl FnOnce() + Send + 'static) -> Self {
+        self.shutdown_hooks.push(Box::new(hook));
+        self
+    }
+
+    // Stops the server from another task; `run` returns once it has drained
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    // Serves the default listener over TLS
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.listeners[0].tls = Some(tls);
+        self
+    }
+
     #[cfg(feature = "config")]
     pub fn from_config(config: &NoxConfig) -> Self {
         let addr = format!("{}:{}", config.server.host, config.server.port)
             .parse()
             .unwrap_or_else(|_| "127.0.0.1:3000".parse().unwrap());
         
-        let router = if let Some(mock_config) = &config.mock {
-            Arc::new(MockRouter::from_config(mock_config))
+        let mut router = if let Some(mock_config) = &config.mock {
+            MockRouter::from_config(mock_config)
         } else {
-            Arc::new(MockRouter::new())
+            MockRouter::new()
         };
 
-        Self { addr, router }
+        // Always run seeded and report the seed, so a failing run can be replayed
+        let seed = config.seed.unwrap_or_else(rand::random);
+        println!("Random seed: {}", seed);
+        router.set_seed(seed);
+
+        for static_config in config.static_files.iter().flatten() {
+            match StaticFileHandler::new(static_config) {
+                Ok(handler) => router.add_static_files(handler),
+                Err(err) => eprintln!(
+                    "Skipping static files at {}: {}",
+                    static_config.prefix.as_deref().unwrap_or("/"),
+                    err
+                ),
+            }
+        }
+
+        #[cfg(feature = "proxy")]
+        if let Some(proxy_config) = &config.proxy {
+            router.set_proxy(ProxyHandler::new(proxy_config));
+        }
+        #[cfg(not(feature = "proxy"))]
+        if config.proxy.is_some() {
+            eprintln!("Warning: 'proxy' requires the proxy feature, ignoring it");
+        }
+
+        let router = Arc::new(router);
+
+        let mut http2 = Http2Options::default();
+        if let Some(http2_config) = &config.server.http2 {
+            http2.enabled = http2_config.enabled.unwrap_or(true);
+            http2.max_concurrent_streams = http2_config
+                .max_concurrent_streams
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_STREAMS);
+            http2.keep_alive_interval = http2_config.keep_alive_interval.map(Duration::from_secs);
+            http2.keep_alive_timeout = http2_config
+                .keep_alive_timeout
+                .map_or(DEFAULT_KEEP_ALIVE_TIMEOUT, Duration::from_secs);
+        }
+
+        #[cfg(not(feature = "tls"))]
+        if config.server.tls.is_some() {
+            eprintln!("Warning: 'tls' requires the tls feature, serving plain HTTP");
+        }
+
+        #[cfg(feature = "tls")]
+        let default_listener = Listener {
+            tls: config.server.tls.clone(),
+            ..Listener::tcp(DEFAULT_LISTENER, addr)
+        };
+        #[cfg(not(feature = "tls"))]
+        let default_listener = Listener::tcp(DEFAULT_LISTENER, addr);
+
+        let mut listeners = vec![default_listener];
+        for listener_config in config.server.listeners.iter().flatten() {
+            if listeners.iter().any(|l| l.name == listener_config.name) {
+                eprintln!("Skipping listener {}: the name is already taken", listener_config.name);
+                continue;
+            }
+            match Listener::from_config(listener_config, &config.server.host) {
+                Ok(listener) => listeners.push(listener),
+                Err(err) => eprintln!("Skipping listener {}: {}", listener_config.name, err),
+            }
+        }
+
+        Self {
+            listeners,
+            router,
+            http2,
+            shutdown: ShutdownHandle::new(),
+            drain_timeout: config.server.drain_timeout.map_or(DEFAULT_DRAIN_TIMEOUT, Duration::from_secs),
+            handle_signals: false,
+            shutdown_hooks: Vec::new(),
+        }
     }
 
+    // Serves until shutdown is requested, then stops accepting, gives
+    // in-flight requests up to the drain timeout and runs the shutdown hooks
     pub async fn run(self) -> Result<()> {
-        let listener = TcpListener::bind(self.addr).await?;
-        println!("NOX Server running on http://{}", self.addr);
+        let bound = self.bind_all().await?;
+        self.serve(bound).await
+    }
 
-        loop {
-            let (stream, _) = listener.accept().await?;
-            let io = TokioIo::new(stream);
-            let router = Arc::clone(&self.router);
-
-            tokio::task::spawn(async move {
-                let service = service_fn(move |req| {
-                    let router = Arc::clone(&router);
-                    async move { router.handle_request(req).await }
-                });
-
-                if let Err(err) = http1::Builder::new()
-                    .serve_connection(io, service)
-                    .await
-                {
-                    eprintln!("Error serving connection: {:?}", err);
+    // Binds 127.0.0.1 on a free port and serves in the background, for tests
+    pub async fn start_ephemeral() -> Result<NoxHandle> {
+        Self::new(SocketAddr::from(([127, 0, 0, 1], 0))).start().await
+    }
+
+    // Binds every listener and serves in the background. The handle knows the
+    // bound address and shuts the server down when dropped
+    pub async fn start(self) -> Result<NoxHandle> {
+        let bound = self.bind_all().await?;
+        let default = &bound[0];
+        let addr = default
+            .addr
+            .ok_or_else(|| Error::Other("the default listener is not a TCP socket".to_string()))?;
+        let base_url = format!("{}://{}", default.scheme, addr);
+
+        let router = Arc::clone(&self.router);
+        let shutdown = self.shutdown.clone();
+        let task = tokio::spawn(self.serve(bound));
+        Ok(NoxHandle::new(addr, base_url, router, shutdown, task))
+    }
+
+    // Every listener is bound before any is served, so a bad address or TLS
+    // setup fails fast instead of leaving the server half up
+    async fn bind_all(&self) -> Result<Vec<BoundListener>> {
+        let mut bound = Vec::with_capacity(self.listeners.len());
+        for listener in &self.listeners {
+            bound.push(listener.bind(self.http2.enabled).await?);
+        }
+        Ok(bound)
+    }
+
+    async fn serve(mut self, bound: Vec<BoundListener>) -> Result<()> {
+        #[cfg(feature = "proxy")]
+        let health_checks = self.router.proxy().and_then(|proxy| proxy.start_health_checks());
+
+        if self.handle_signals {
+            let shutdown = self.shutdown.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = shutdown::signal() => {
+                        println!("Shutting down");
+                        shutdown.shutdown();
+                    }
+                    _ = shutdown.requested() => {}
                 }
             });
         }
+
+        let connections = Arc::new(Connections {
+            builder: self.connection_builder(),
+            router: Arc::clone(&self.router),
+            shutdown: self.shutdown.clone(),
+            abort: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+        });
+
+        // Accept loops return once shutdown is requested. An accept error
+        // takes the whole server down, draining like any other shutdown
+        let accept_loops = bound
+            .into_iter()
+            .map(|listener| listener.accept_loop(Arc::clone(&connections)));
+        let result = futures::future::try_join_all(accept_loops).await;
+        self.shutdown.shutdown();
+
+        connections.tracker.close();
+        if !connections.tracker.is_empty() {
+            println!("Draining {} connections", connections.tracker.len());
+            if tokio::time::timeout(self.drain_timeout, connections.tracker.wait()).await.is_err() {
+                eprintln!("Drain timeout reached, closing {} connections", connections.tracker.len());
+                connections.abort.cancel();
+                connections.tracker.wait().await;
+            }
+        }
+
+        #[cfg(feature = "proxy")]
+        if let Some(health_checks) = health_checks {
+            health_checks.abort();
+        }
+        #[cfg(unix)]
+        for listener in &self.listeners {
+            if let Bind::Unix(path) = &listener.bind {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        for hook in self.shutdown_hooks.drain(..) {
+            hook();
+        }
+
+        result?;
+        println!("NOX Server stopped");
+        Ok(())
+    }
+
+    // Detects HTTP/2 from the client's connection preface, so h2c clients
+    // with prior knowledge and HTTP/1.1 clients share the port
+    fn connection_builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        if !self.http2.enabled {
+            return builder.http1_only();
+        }
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(self.http2.max_concurrent_streams)
+            .keep_alive_interval(self.http2.keep_alive_interval)
+            .keep_alive_timeout(self.http2.keep_alive_timeout);
+        builder
     }
 }
 
+impl Listener {
+    pub fn tcp(name: impl Into<String>, addr: SocketAddr) -> Self {
+        Self {
+            name: name.into(),
+            bind: Bind::Tcp(addr),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn unix(name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            bind: Bind::Unix(path.into()),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    #[cfg(feature = "config")]
+    fn from_config(config: &ListenerConfig, default_host: &str) -> std::result::Result<Self, String> {
+        let listener = match (config.port, &config.unix) {
+            (Some(_), Some(_)) => return Err("set either port or unix, not both".to_string()),
+            (None, None) => return Err("needs a port or a unix socket path".to_string()),
+            (Some(port), None) => {
+                let host = config.host.as_deref().unwrap_or(default_host);
+                let ip: IpAddr = host.parse().map_err(|_| format!("invalid host '{}'", host))?;
+                Self::tcp(&config.name, SocketAddr::new(ip, port))
+            }
+            #[cfg(unix)]
+            (None, Some(path)) => Self::unix(&config.name, path),
+            #[cfg(not(unix))]
+            (None, Some(_)) => return Err("unix sockets are not supported on this platform".to_string()),
+        };
+
+        #[cfg(feature = "tls")]
+        let listener = Self { tls: config.tls.clone(), ..listener };
+        #[cfg(not(feature = "tls"))]
+        if config.tls.is_some() {
+            eprintln!("Warning: 'tls' on listener {} requires the tls feature, serving plain HTTP", config.name);
+        }
+        Ok(listener)
+    }
+
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    async fn bind(&self, alpn_h2: bool) -> Result<BoundListener> {
+        // Certificates are loaded (or generated) before binding, so a bad
+        // TLS setup fails fast instead of falling back to plain HTTP
+        #[cfg(feature = "tls")]
+        let tls = match (&self.tls, &self.bind) {
+            (Some(config), Bind::Tcp(addr)) => Some(crate::tls::acceptor(config, &addr.ip().to_string(), alpn_h2)?),
+            #[cfg(unix)]
+            (Some(config), Bind::Unix(_)) => Some(crate::tls::acceptor(config, "", alpn_h2)?),
+            (None, _) => None,
+        };
+        #[cfg(feature = "tls")]
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        #[cfg(not(feature = "tls"))]
+        let scheme = "http";
+
+        let socket = match &self.bind {
+            Bind::Tcp(addr) => TcpListener::bind(addr).await.map(Socket::Tcp),
+            #[cfg(unix)]
+            Bind::Unix(path) => bind_unix(path).map(Socket::Unix),
+        };
+        let socket = socket.map_err(|err| Error::Other(format!("Failed to bind {}: {}", self.bind, err)))?;
+
+        let (addr, url) = match (&socket, &self.bind) {
+            (Socket::Tcp(listener), _) => {
+                let addr = listener.local_addr()?;
+                (Some(addr), format!("{}://{}", scheme, addr))
+            }
+            #[cfg(unix)]
+            (Socket::Unix(_), bind) => (None, format!("{} over {}", scheme, bind)),
+        };
+        match self.name.as_str() {
+            DEFAULT_LISTENER => println!("NOX Server running on {}", url),
+            name => println!("NOX Server running on {} ({})", url, name),
+        }
+
+        Ok(BoundListener {
+            name: Arc::from(self.name.as_str()),
+            socket,
+            addr,
+            scheme,
+            #[cfg(feature = "tls")]
+            tls,
+        })
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bind::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Bind::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// A socket file left behind by an earlier run would make the bind fail
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+impl BoundListener {
+    async fn accept_loop(self, connections: Arc<Connections>) -> Result<()> {
+        loop {
+            tokio::select! {
+                result = self.accept(&connections) => result?,
+                _ = connections.shutdown.requested() => return Ok(()),
+            }
+        }
+    }
+
+    async fn accept(&self, connections: &Arc<Connections>) -> Result<()> {
+        match &self.socket {
+            Socket::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                self.spawn(stream, connections);
+            }
+            #[cfg(unix)]
+            Socket::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                self.spawn(stream, connections);
+            }
+        }
+        Ok(())
+    }
+
+    fn spawn<S>(&self, stream: S, connections: &Arc<Connections>)
+    where
+        S: AsyncRead + AsyncWrite + AbortiveClose + Unpin + Send + 'static,
+    {
+        let abort = connections.abort.clone();
+        let tracker = connections.tracker.clone();
+        let connections = Arc::clone(connections);
+        let name = Arc::clone(&self.name);
+        #[cfg(feature = "tls")]
+        let tls = self.tls.clone();
+
+        let task = async move {
+            // The handshake runs in the connection's task so a slow client
+            // can't hold up the accept loop
+            #[cfg(feature = "tls")]
+            if let Some(acceptor) = tls {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => serve_connection(&connections, name, stream).await,
+                    Err(err) => eprintln!("TLS handshake failed: {}", err),
+                }
+                return;
+            }
+
+            serve_connection(&connections, name, stream).await;
+        };
+
+        tracker.spawn(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = abort.cancelled() => {}
+            }
+        });
+    }
+}
+
+async fn serve_connection<S>(connections: &Connections, listener: Arc<str>, stream: S)
+where
+    S: AsyncRead + AsyncWrite + AbortiveClose + Unpin + Send + 'static,
+{
+    let router = Arc::clone(&connections.router);
+    let faults = FaultHandle::new();
+    let io = TokioIo::new(FaultIo::new(stream, faults.clone()));
+
+    let service = service_fn(move |mut req| {
+        let router = Arc::clone(&router);
+        let faults = faults.clone();
+        req.extensions_mut().insert(ListenerName(Arc::clone(&listener)));
+        async move {
+            let response = router.handle_request(req).await?;
+            // Transport faults take over the socket before hyper writes the response
+            faults.arm(&response);
+            Ok::<_, std::convert::Infallible>(response)
+        }
+    });
+
+    let connection = connections.builder.serve_connection(io, service);
+    tokio::pin!(connection);
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = connections.shutdown.requested() => {
+            // Lets requests in flight finish and closes the connection after
+            connection.as_mut().graceful_shutdown();
+            // A connection that hadn't sent a request yet is cancelled, which is expected
+            connection.await.or_else(|err| match err.downcast_ref::<std::io::Error>() {
+                Some(io_err) if io_err.kind() == std::io::ErrorKind::Interrupted => Ok(()),
+                _ => Err(err),
+            })
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("Error serving connection: {:?}", err);
+    }
+}
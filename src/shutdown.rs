@@ -0,0 +1,52 @@
+use tokio_util::sync::CancellationToken;
+
+// Stops a running server. Cloning shares the handle, so tests and signal
+// handlers can each hold one
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    token: CancellationToken,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Stops accepting connections and lets in-flight requests finish. Calling
+    // it again does nothing
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    // Resolves once shutdown has been requested
+    pub async fn requested(&self) {
+        self.token.cancelled().await
+    }
+}
+
+// Resolves on SIGINT or SIGTERM (Ctrl-C elsewhere)
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(err) => {
+                eprintln!("Failed to listen for SIGTERM: {}", err);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,598 @@
+use crate::body::{self, NoxBody};
+use crate::config::StaticFilesConfig;
+use crate::context::RequestContext;
+use hyper::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, LOCATION, PRAGMA, RANGE, VARY,
+};
+use hyper::{Method, Response, StatusCode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use std::fs::Metadata;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_INDEX_FILES: &[&str] = &["index.html"];
+const DEV_CACHE_CONTROL: &str = "no-cache, no-store, must-revalidate";
+
+// Precompressed siblings in order of preference, by extension and encoding
+const PRECOMPRESSED: &[(&str, &str)] = &[("br", "br"), ("gz", "gzip")];
+
+// Characters left alone when a file name is put in a listing link
+const LINK_SAFE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+// Serves the files under one directory at a path prefix
+pub struct StaticFileHandler {
+    prefix: String,
+    // Canonical, so resolved files can be checked against it
+    root_dir: PathBuf,
+    index_files: Vec<String>,
+    directory_listing: bool,
+    cache_control: Option<String>,
+    cache_rules: Vec<(Glob, String)>,
+    spa_fallback: Option<String>,
+    precompressed: bool,
+    dev: bool,
+}
+
+#[derive(Debug)]
+struct Glob {
+    regex: Regex,
+    // Patterns without a slash are matched against the file name only
+    name_only: bool,
+}
+
+impl StaticFileHandler {
+    pub fn new(config: &StaticFilesConfig) -> Result<Self, String> {
+        let root_dir = config
+            .root_dir
+            .canonicalize()
+            .map_err(|e| format!("root_dir {}: {}", config.root_dir.display(), e))?;
+        if !root_dir.is_dir() {
+            return Err(format!("root_dir {} is not a directory", root_dir.display()));
+        }
+
+        Ok(Self {
+            prefix: config.prefix.as_deref().unwrap_or("/").trim_end_matches('/').to_string(),
+            root_dir,
+            index_files: match &config.index_files {
+                Some(names) => names.clone(),
+                None => DEFAULT_INDEX_FILES.iter().map(|n| n.to_string()).collect(),
+            },
+            directory_listing: config.directory_listing.unwrap_or(false),
+            cache_control: config.cache_control.clone(),
+            cache_rules: config
+                .cache_rules
+                .iter()
+                .flatten()
+                .map(|rule| Ok((Glob::new(&rule.pattern)?, rule.cache_control.clone())))
+                .collect::<Result<_, String>>()?,
+            spa_fallback: config.spa_fallback.clone(),
+            precompressed: config.precompressed.unwrap_or(false),
+            dev: config.dev.unwrap_or(false),
+        })
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    // Returns None when there is nothing under this mount for the request, so
+    // it can fall through to the next handler. The SPA fallback is left to
+    // handle_spa_fallback, which runs once the proxy has had its chance
+    pub async fn handle(&self, ctx: &RequestContext) -> Option<Response<NoxBody>> {
+        let rest = self.rest_of_path(ctx)?;
+        let path = self.resolve(rest)?;
+        let metadata = tokio::fs::metadata(&path).await.ok()?;
+        if metadata.is_file() {
+            return Some(self.serve_file(ctx, &path, &metadata).await);
+        }
+
+        let index = self.find_index(&path).await;
+        if index.is_none() && !self.directory_listing {
+            return None;
+        }
+        // Relative links in the page only work from a URL ending in a slash
+        if !ctx.path().ends_with('/') {
+            let query = ctx.uri.query().map(|q| format!("?{}", q)).unwrap_or_default();
+            return Some(
+                Response::builder()
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header(LOCATION, format!("{}/{}", ctx.path(), query))
+                    .body(body::empty())
+                    .unwrap(),
+            );
+        }
+
+        Some(match index {
+            Some((index, metadata)) => self.serve_file(ctx, &index, &metadata).await,
+            None => self.list_directory(ctx, &path).await,
+        })
+    }
+
+    // The part of a GET or HEAD path below this mount
+    fn rest_of_path<'a>(&self, ctx: &'a RequestContext) -> Option<&'a str> {
+        if ctx.method != Method::GET && ctx.method != Method::HEAD {
+            return None;
+        }
+        let rest = ctx.path().strip_prefix(self.prefix.as_str())?;
+        (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+    }
+
+    // Maps the rest of the URL path onto the root directory. Every segment
+    // must decode to a plain file name, and the result (after following
+    // symlinks) must still be inside the root
+    fn resolve(&self, rest: &str) -> Option<PathBuf> {
+        let mut path = self.root_dir.clone();
+        for segment in rest.split('/').filter(|s| !s.is_empty()) {
+            let decoded = percent_decode_str(segment).decode_utf8().ok()?;
+            if decoded.contains(['\\', '\0']) {
+                return None;
+            }
+            let mut components = Path::new(decoded.as_ref()).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(name)), None) => path.push(name),
+                _ => return None,
+            }
+        }
+        self.within_root(&path)
+    }
+
+    fn within_root(&self, path: &Path) -> Option<PathBuf> {
+        let canonical = path.canonicalize().ok()?;
+        canonical.starts_with(&self.root_dir).then_some(canonical)
+    }
+
+    // Client-side routes like /users/42 get the app's entry page; anything
+    // that looks like an asset (has an extension) stays a 404
+    pub async fn handle_spa_fallback(&self, ctx: &RequestContext) -> Option<Response<NoxBody>> {
+        let fallback = self.spa_fallback.as_ref()?;
+        let rest = self.rest_of_path(ctx)?;
+        let name = rest.rsplit('/').find(|s| !s.is_empty()).unwrap_or_default();
+        if name.contains('.') {
+            return None;
+        }
+
+        let path = self.within_root(&self.root_dir.join(fallback))?;
+        let metadata = tokio::fs::metadata(&path).await.ok().filter(|m| m.is_file())?;
+        Some(self.serve_file(ctx, &path, &metadata).await)
+    }
+
+    async fn find_index(&self, dir: &Path) -> Option<(PathBuf, Metadata)> {
+        for name in &self.index_files {
+            let Some(path) = self.within_root(&dir.join(name)) else {
+                continue;
+            };
+            if let Ok(metadata) = tokio::fs::metadata(&path).await {
+                if metadata.is_file() {
+                    return Some((path, metadata));
+                }
+            }
+        }
+        None
+    }
+
+    async fn serve_file(&self, ctx: &RequestContext, path: &Path, metadata: &Metadata) -> Response<NoxBody> {
+        let content_type = mime_guess::from_path(path).first_or_octet_stream();
+        let mut builder = self.cache_headers(Response::builder(), path);
+
+        // The compressed sibling, when there is one, stands in for the file
+        // from here on: its length, ETag and byte ranges are what get sent
+        let compressed = if self.precompressed {
+            builder = builder.header(VARY, "accept-encoding");
+            self.find_precompressed(ctx, path).await
+        } else {
+            None
+        };
+        let (path, metadata) = match &compressed {
+            Some((encoding, path, metadata)) => {
+                builder = builder.header(CONTENT_ENCODING, *encoding);
+                (path.as_path(), metadata)
+            }
+            None => (path, metadata),
+        };
+
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+        let etag = etag(len, modified);
+
+        builder = builder.header(ETAG, &etag).header(ACCEPT_RANGES, "bytes");
+        if let Some(modified) = modified {
+            builder = builder.header(LAST_MODIFIED, httpdate::fmt_http_date(modified));
+        }
+
+        if is_not_modified(ctx, &etag, modified) {
+            return builder.status(StatusCode::NOT_MODIFIED).body(body::empty()).unwrap();
+        }
+
+        builder = builder.header(CONTENT_TYPE, content_type.as_ref());
+
+        let range = match ctx.headers.get(RANGE).and_then(|v| v.to_str().ok()) {
+            Some(range) if if_range_matches(ctx, &etag, modified) => parse_range(range, len),
+            _ => Ok(None),
+        };
+        let (start, count) = match range {
+            Ok(Some((start, end))) => {
+                builder = builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len));
+                (start, end - start + 1)
+            }
+            Ok(None) => (0, len),
+            Err(()) => {
+                return builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", len))
+                    .body(body::empty())
+                    .unwrap();
+            }
+        };
+        builder = builder.header(CONTENT_LENGTH, count);
+
+        if ctx.method == Method::HEAD {
+            return builder.body(body::empty()).unwrap();
+        }
+        match body::file(path, start, count).await {
+            Ok(body) => builder.body(body).unwrap(),
+            Err(err) => {
+                eprintln!("Failed to read {}: {}", path.display(), err);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(body::full("Failed to read file"))
+                    .unwrap()
+            }
+        }
+    }
+
+    // The best sibling like app.js.br or app.js.gz that the client accepts
+    async fn find_precompressed(
+        &self,
+        ctx: &RequestContext,
+        path: &Path,
+    ) -> Option<(&'static str, PathBuf, Metadata)> {
+        let accepted: Vec<&str> = ctx
+            .headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|coding| {
+                let mut parts = coding.split(';').map(str::trim);
+                let name = parts.next()?;
+                let refused = parts.any(|p| p.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+                (!refused).then_some(name)
+            })
+            .collect();
+
+        for (extension, encoding) in PRECOMPRESSED {
+            if !accepted.iter().any(|name| name.eq_ignore_ascii_case(encoding)) {
+                continue;
+            }
+            let mut sibling = path.as_os_str().to_owned();
+            sibling.push(format!(".{}", extension));
+            let Some(sibling) = self.within_root(Path::new(&sibling)) else {
+                continue;
+            };
+            if let Ok(metadata) = tokio::fs::metadata(&sibling).await {
+                if metadata.is_file() {
+                    return Some((encoding, sibling, metadata));
+                }
+            }
+        }
+        None
+    }
+
+    fn cache_headers(&self, builder: http::response::Builder, path: &Path) -> http::response::Builder {
+        if self.dev {
+            return builder
+                .header(CACHE_CONTROL, DEV_CACHE_CONTROL)
+                .header(PRAGMA, "no-cache")
+                .header(EXPIRES, "0");
+        }
+
+        let relative = path.strip_prefix(&self.root_dir).unwrap_or(path);
+        let relative: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+        let cache_control = self
+            .cache_rules
+            .iter()
+            .find(|(glob, _)| glob.matches(&relative.join("/")))
+            .map(|(_, value)| value)
+            .or(self.cache_control.as_ref());
+
+        match cache_control {
+            Some(value) => builder.header(CACHE_CONTROL, value),
+            None => builder,
+        }
+    }
+
+    async fn list_directory(&self, ctx: &RequestContext, dir: &Path) -> Response<NoxBody> {
+        let mut entries = Vec::new();
+        if let Ok(mut read_dir) = tokio::fs::read_dir(dir).await {
+            while let Ok(Some(entry)) = read_dir.next_entry().await {
+                let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+                entries.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+            }
+        }
+        // Directories first, then alphabetical
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let title = escape_html(&percent_decode_str(ctx.path()).decode_utf8_lossy());
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+            title
+        );
+        if dir != self.root_dir {
+            html.push_str("<li><a href=\"../\">../</a></li>\n");
+        }
+        for (name, is_dir) in &entries {
+            let slash = if *is_dir { "/" } else { "" };
+            html.push_str(&format!(
+                "<li><a href=\"{}{}\">{}{}</a></li>\n",
+                utf8_percent_encode(name, LINK_SAFE),
+                slash,
+                escape_html(name),
+                slash
+            ));
+        }
+        html.push_str("</ul>\n</body>\n</html>\n");
+
+        let builder = self
+            .cache_headers(Response::builder(), dir)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CONTENT_LENGTH, html.len());
+        if ctx.method == Method::HEAD {
+            return builder.body(body::empty()).unwrap();
+        }
+        builder.body(body::full(html)).unwrap()
+    }
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.trim_start_matches('/');
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        let mut in_alternatives = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    // "**/" also matches no directories at all
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '{' if !in_alternatives => {
+                    in_alternatives = true;
+                    regex.push_str("(?:");
+                }
+                '}' if in_alternatives => {
+                    in_alternatives = false;
+                    regex.push(')');
+                }
+                ',' if in_alternatives => regex.push('|'),
+                other => regex.push_str(&regex::escape(&other.to_string())),
+            }
+        }
+        regex.push('$');
+
+        Ok(Self {
+            regex: Regex::new(&regex).map_err(|e| format!("invalid cache rule pattern '{}': {}", pattern, e))?,
+            name_only: !pattern.contains('/'),
+        })
+    }
+
+    fn matches(&self, relative: &str) -> bool {
+        if self.name_only {
+            self.regex.is_match(relative.rsplit('/').next().unwrap_or_default())
+        } else {
+            self.regex.is_match(relative)
+        }
+    }
+}
+
+// Size and modification time change whenever the content is replaced
+fn etag(len: u64, modified: Option<SystemTime>) -> String {
+    let nanos = modified
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!("\"{:x}-{:x}\"", len, nanos)
+}
+
+// If-None-Match wins over If-Modified-Since when both are sent
+fn is_not_modified(ctx: &RequestContext, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = ctx.headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        return tags
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+
+    let since = ctx
+        .headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since, modified) {
+        // HTTP dates only have whole seconds
+        (Some(since), Some(modified)) => unix_secs(modified) <= unix_secs(since),
+        _ => false,
+    }
+}
+
+// A Range is only honoured if the client's copy is still the current one
+fn if_range_matches(ctx: &RequestContext, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(condition) = ctx.headers.get(IF_RANGE).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    if condition.starts_with('"') || condition.starts_with("W/") {
+        return condition == etag;
+    }
+    match (httpdate::parse_http_date(condition), modified) {
+        (Ok(date), Some(modified)) => unix_secs(modified) == unix_secs(date),
+        _ => false,
+    }
+}
+
+// Parses a single byte range into inclusive bounds. Ok(None) means serve the
+// whole file: the header is malformed, uses another unit or asks for several
+// ranges. Err means the range starts past the end of the file
+fn parse_range(header: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+
+    match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => {
+            if start < len {
+                Ok(Some((start, end.min(len - 1))))
+            } else {
+                Err(())
+            }
+        }
+        (Ok(start), Err(_)) if end.is_empty() => {
+            if start < len {
+                Ok(Some((start, len - 1)))
+            } else {
+                Err(())
+            }
+        }
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix > 0 && len > 0 {
+                Ok(Some((len.saturating_sub(suffix), len - 1)))
+            } else {
+                Err(())
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::HeaderMap;
+    use hyper::Uri;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn handler(root: &Path) -> StaticFileHandler {
+        StaticFileHandler::new(&StaticFilesConfig {
+            prefix: None,
+            root_dir: root.to_path_buf(),
+            index_files: None,
+            directory_listing: None,
+            cache_control: None,
+            cache_rules: None,
+            spa_fallback: None,
+            precompressed: None,
+            dev: None,
+        })
+        .unwrap()
+    }
+
+    // A root holding a.txt and sub/b.txt, next to a secret.txt outside it
+    fn tree() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("sub/b.txt"), "b").unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        (dir, root)
+    }
+
+    #[test]
+    fn test_parse_range_bounds() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Ok(Some((900, 999))));
+        // Open-ended runs to the last byte
+        assert_eq!(parse_range("bytes=500-", 1000), Ok(Some((500, 999))));
+        // Suffixes count back from the end, and a long one is the whole file
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok(Some((0, 999))));
+    }
+
+    #[test]
+    fn test_parse_range_unsatisfiable() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=2000-3000", 1000), Err(()));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(()));
+        assert_eq!(parse_range("bytes=0-", 0), Err(()));
+    }
+
+    #[test]
+    fn test_parse_range_falls_back_to_whole_file() {
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=5-2", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=a-b", 1000), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_range_past_end_is_416() {
+        let (_dir, root) = tree();
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, "bytes=10-".parse().unwrap());
+        let ctx = RequestContext::new(Method::GET, Uri::from_static("/a.txt"), headers, Bytes::new());
+
+        let response = handler(&root).handle(&ctx).await.unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[test]
+    fn test_resolve_rejects_traversal() {
+        let (_dir, root) = tree();
+        let handler = handler(&root);
+
+        assert!(handler.resolve("/a.txt").is_some());
+        assert!(handler.resolve("/sub/b.txt").is_some());
+        assert!(handler.resolve("/sub/../a.txt").is_none());
+        assert!(handler.resolve("/../secret.txt").is_none());
+        assert!(handler.resolve("/%2e%2e/secret.txt").is_none());
+        assert!(handler.resolve("/%2E%2E/secret.txt").is_none());
+        assert!(handler.resolve("/..%2fsecret.txt").is_none());
+        assert!(handler.resolve("/sub/..%2f..%2fsecret.txt").is_none());
+        assert!(handler.resolve("/sub%5c..%5c..%5csecret.txt").is_none());
+        assert!(handler.resolve("/a.txt%00").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_rejects_symlinks_out_of_root() {
+        let (dir, root) = tree();
+        std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("leak.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), root.join("up")).unwrap();
+        std::os::unix::fs::symlink(root.join("sub/b.txt"), root.join("alias.txt")).unwrap();
+        let handler = handler(&root);
+
+        assert!(handler.resolve("/leak.txt").is_none());
+        assert!(handler.resolve("/up/secret.txt").is_none());
+        // Links that stay inside the root are followed
+        assert_eq!(handler.resolve("/alias.txt"), Some(handler.root_dir.join("sub/b.txt")));
+    }
+}
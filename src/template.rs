@@ -0,0 +1,253 @@
+use crate::context::RequestContext;
+use crate::error::{Error, Result};
+use crate::pattern::PathParams;
+use base64::Engine as _;
+use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde_json::{json, Map, Value};
+
+pub struct TemplateEngine {
+    handlebars: Handlebars<'static>,
+}
+
+impl TemplateEngine {
+    pub fn new() -> Self {
+        let mut handlebars = Handlebars::new();
+
+        // Mock bodies are usually JSON, so never HTML-escape substitutions
+        handlebars.register_escape_fn(handlebars::no_escape);
+
+        handlebars.register_helper("uuid", Box::new(uuid_helper));
+        handlebars.register_helper("random", Box::new(random_helper));
+        handlebars.register_helper("timestamp", Box::new(timestamp_helper));
+        handlebars.register_helper("base64", Box::new(base64_helper));
+        handlebars.register_helper("url_encode", Box::new(url_encode_helper));
+        handlebars.register_helper("json", Box::new(json_helper));
+        handlebars.register_helper("fake_data", Box::new(fake_data_helper));
+
+        Self { handlebars }
+    }
+
+    // Parse a template without rendering it, so syntax errors surface at load time
+    pub fn validate(template: &str) -> std::result::Result<(), String> {
+        handlebars::Template::compile(template)
+            .map(|_| ())
+            .map_err(|e| format!("invalid template: {}", e))
+    }
+
+    pub fn render_string(&self, template: &str, context: &Value) -> Result<String> {
+        self.handlebars
+            .render_template(template, context)
+            .map_err(Error::Template)
+    }
+
+    // The data available to response templates:
+    //   path.*, query.*, headers.*, body (parsed JSON), body_text,
+    //   request.{method,path,url}, timestamp, random.{uuid,number,boolean}
+    pub fn context_for(ctx: &RequestContext, params: &PathParams) -> Value {
+        let mut query = Map::new();
+        for (key, value) in &ctx.query {
+            query
+                .entry(key.clone())
+                .or_insert_with(|| Value::String(value.clone()));
+        }
+
+        let mut headers = Map::new();
+        for name in ctx.headers.keys() {
+            if let Some(value) = ctx.header_values(name.as_str()).next() {
+                headers.insert(name.as_str().to_string(), Value::String(value.to_string()));
+            }
+        }
+
+        json!({
+            "request": {
+                "method": ctx.method.as_str(),
+                "path": ctx.path(),
+                "url": ctx.uri.to_string(),
+            },
+            "path": params,
+            "query": query,
+            "headers": headers,
+            "body": ctx.json_body().cloned().unwrap_or(Value::Null),
+            "body_text": ctx.body_text(),
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "random": {
+                "uuid": uuid::Uuid::new_v4().to_string(),
+                "number": rand::random::<u32>(),
+                "boolean": rand::random::<bool>(),
+            },
+        })
+    }
+}
+
+impl Default for TemplateEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn uuid_helper(
+    _: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    out.write(&uuid::Uuid::new_v4().to_string())?;
+    Ok(())
+}
+
+fn random_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let mut rng = rand::thread_rng();
+
+    let value = match h.param(0).and_then(|v| v.value().as_str()) {
+        Some("int") => {
+            let min = h.param(1).and_then(|v| v.value().as_i64()).unwrap_or(0);
+            let max = h.param(2).and_then(|v| v.value().as_i64()).unwrap_or(100);
+            if min >= max {
+                min.to_string()
+            } else {
+                rng.gen_range(min..=max).to_string()
+            }
+        }
+        Some("float") => rng.gen::<f64>().to_string(),
+        Some("bool") => rng.gen::<bool>().to_string(),
+        Some("string") => {
+            let length = h.param(1).and_then(|v| v.value().as_u64()).unwrap_or(10) as usize;
+            (&mut rng)
+                .sample_iter(rand::distributions::Alphanumeric)
+                .take(length)
+                .map(char::from)
+                .collect()
+        }
+        _ => rng.gen::<u32>().to_string(),
+    };
+
+    out.write(&value)?;
+    Ok(())
+}
+
+fn timestamp_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let format = h.param(0).and_then(|v| v.value().as_str()).unwrap_or("rfc3339");
+
+    let now = chrono::Utc::now();
+    let timestamp = match format {
+        "unix" => now.timestamp().to_string(),
+        "unix_ms" => now.timestamp_millis().to_string(),
+        "rfc3339" => now.to_rfc3339(),
+        "iso8601" => now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        custom_format => now.format(custom_format).to_string(),
+    };
+
+    out.write(&timestamp)?;
+    Ok(())
+}
+
+fn base64_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    if let Some(input) = h.param(0).and_then(|v| v.value().as_str()) {
+        out.write(&base64::engine::general_purpose::STANDARD.encode(input))?;
+    }
+    Ok(())
+}
+
+fn url_encode_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    if let Some(input) = h.param(0).and_then(|v| v.value().as_str()) {
+        let encoded: String = url::form_urlencoded::byte_serialize(input.as_bytes()).collect();
+        out.write(&encoded)?;
+    }
+    Ok(())
+}
+
+fn json_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    if let Some(value) = h.param(0) {
+        out.write(&serde_json::to_string(value.value()).unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+fn fake_data_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let mut rng = rand::thread_rng();
+
+    let value = match h.param(0).and_then(|v| v.value().as_str()).unwrap_or("name") {
+        "name" => format!("{} {}", pick(&mut rng, FIRST_NAMES), pick(&mut rng, LAST_NAMES)),
+        "first_name" => pick(&mut rng, FIRST_NAMES).to_string(),
+        "last_name" => pick(&mut rng, LAST_NAMES).to_string(),
+        "email" => format!(
+            "{}.{}@{}",
+            pick(&mut rng, FIRST_NAMES).to_lowercase(),
+            pick(&mut rng, LAST_NAMES).to_lowercase(),
+            pick(&mut rng, DOMAINS)
+        ),
+        "phone" => format!(
+            "+1-{:03}-{:03}-{:04}",
+            rng.gen_range(200..1000),
+            rng.gen_range(200..1000),
+            rng.gen_range(0..10000)
+        ),
+        "address" => format!("{} {}", rng.gen_range(1..10000), pick(&mut rng, STREETS)),
+        "company" => format!("{} {}", pick(&mut rng, COMPANY_PREFIXES), pick(&mut rng, COMPANY_SUFFIXES)),
+        "lorem" => {
+            let words = h.param(1).and_then(|v| v.value().as_u64()).unwrap_or(5) as usize;
+            (0..words)
+                .map(|_| pick(&mut rng, LOREM))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        _ => "Unknown".to_string(),
+    };
+
+    out.write(&value)?;
+    Ok(())
+}
+
+fn pick<'a>(rng: &mut impl Rng, values: &[&'a str]) -> &'a str {
+    values.choose(rng).copied().unwrap_or_default()
+}
+
+const FIRST_NAMES: &[&str] = &["John", "Jane", "Alice", "Bob", "Charlie", "Diana", "Eve", "Frank"];
+const LAST_NAMES: &[&str] = &["Smith", "Johnson", "Williams", "Brown", "Jones", "Garcia", "Miller", "Davis"];
+const DOMAINS: &[&str] = &["example.com", "test.org", "demo.net", "sample.io"];
+const STREETS: &[&str] = &["Main St", "Oak Ave", "Park Rd", "First St", "Second Ave", "Elm St"];
+const COMPANY_PREFIXES: &[&str] = &["Tech", "Digital", "Global", "Smart", "Advanced", "Future"];
+const COMPANY_SUFFIXES: &[&str] = &["Solutions", "Systems", "Corp", "Inc", "Ltd", "Technologies"];
+const LOREM: &[&str] = &[
+    "lorem", "ipsum", "dolor", "sit", "amet", "consectetur", "adipiscing", "elit", "sed", "do",
+    "eiusmod", "tempor", "incididunt", "ut", "labore", "et", "dolore", "magna", "aliqua",
+];
@@ -0,0 +1,159 @@
+use crate::config::TlsConfig;
+use crate::error::{Error, Result};
+use crate::fault::AbortiveClose;
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+const DEFAULT_CA_OUTPUT: &str = "nox-ca.pem";
+
+// Names the generated certificate is valid for, besides the configured host
+const SELF_SIGNED_NAMES: &[&str] = &["localhost", "127.0.0.1", "::1"];
+
+// One CA per process, so every self-signed listener is trusted through the
+// same CA file
+static TEST_CA: Mutex<Option<Arc<TestCa>>> = Mutex::new(None);
+
+struct TestCa {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+}
+
+// Builds the acceptor for a listener. `alpn_h2` advertises HTTP/2 alongside
+// HTTP/1.1
+pub fn acceptor(config: &TlsConfig, host: &str, alpn_h2: bool) -> Result<TlsAcceptor> {
+    let (certs, key) = if config.self_signed.unwrap_or(false) {
+        self_signed(
+            host,
+            config.names.as_deref().unwrap_or_default(),
+            config.ca_output.as_deref().unwrap_or(Path::new(DEFAULT_CA_OUTPUT)),
+        )?
+    } else {
+        let (Some(cert_path), Some(key_path)) = (&config.cert_path, &config.key_path) else {
+            return Err(Error::Config("tls needs cert_path and key_path, or self_signed".to_string()));
+        };
+        (load_certs(cert_path)?, load_key(key_path)?)
+    };
+
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+
+    let builder = match &config.client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca_path)? {
+                roots.add(cert).map_err(tls_error)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if config.client_auth_optional.unwrap_or(false) {
+                verifier.allow_unauthenticated().build()
+            } else {
+                verifier.build()
+            };
+            builder.with_client_cert_verifier(verifier.map_err(tls_error)?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(certs, key).map_err(tls_error)?;
+    server_config.alpn_protocols = if alpn_h2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+// Generates a certificate for the server signed by the process's test CA.
+// Only the CA is written out, for clients to trust; the keys live and die
+// with the process
+fn self_signed(
+    host: &str,
+    extra_names: &[String],
+    ca_output: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let ca = test_ca()?;
+
+    let mut names: Vec<String> = SELF_SIGNED_NAMES.iter().map(|n| n.to_string()).collect();
+    let wanted = std::iter::once(host).chain(extra_names.iter().map(String::as_str));
+    for name in wanted {
+        if !name.is_empty() && !names.iter().any(|n| n == name) && name != "0.0.0.0" && name != "::" {
+            names.push(name.to_string());
+        }
+    }
+    let mut params = CertificateParams::new(names).map_err(tls_error)?;
+    params.distinguished_name.push(DnType::CommonName, "NOX Server");
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let key = KeyPair::generate().map_err(tls_error)?;
+    let cert = params.signed_by(&key, &ca.cert, &ca.key).map_err(tls_error)?;
+
+    if let Some(parent) = ca_output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(ca_output, ca.cert.pem())?;
+    println!("Generated a self-signed certificate; trust the CA at {}", ca_output.display());
+
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+    Ok((vec![cert.der().clone()], key))
+}
+
+fn test_ca() -> Result<Arc<TestCa>> {
+    let mut slot = TEST_CA.lock().unwrap();
+    if let Some(ca) = slot.as_ref() {
+        return Ok(Arc::clone(ca));
+    }
+
+    let mut params = CertificateParams::new(Vec::<String>::new()).map_err(tls_error)?;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, "NOX Test CA");
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let key = KeyPair::generate().map_err(tls_error)?;
+    let cert = params.self_signed(&key).map_err(tls_error)?;
+
+    let ca = Arc::new(TestCa { cert, key });
+    *slot = Some(Arc::clone(&ca));
+    Ok(ca)
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(std::fs::File::open(path).map_err(|e| file_error(path, e))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| file_error(path, e))?;
+    if certs.is_empty() {
+        return Err(Error::Config(format!("no certificates found in {}", path.display())));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(std::fs::File::open(path).map_err(|e| file_error(path, e))?);
+    rustls_pemfile::private_key(&mut reader)
+        .map_err(|e| file_error(path, e))?
+        .ok_or_else(|| Error::Config(format!("no private key found in {}", path.display())))
+}
+
+fn file_error(path: &Path, err: std::io::Error) -> Error {
+    Error::Config(format!("{}: {}", path.display(), err))
+}
+
+fn tls_error(err: impl std::fmt::Display) -> Error {
+    Error::Config(format!("TLS setup failed: {}", err))
+}
+
+impl<S: AbortiveClose> AbortiveClose for TlsStream<S> {
+    fn abort(&self) {
+        self.get_ref().0.abort();
+    }
+}
@@ -0,0 +1,255 @@
+use crate::body::{self, NoxBody};
+use crate::config::TransformRule;
+use crate::jsonpath::JsonPath;
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH};
+use hyper::{Response, StatusCode};
+use regex::Regex;
+use serde_json::Value;
+
+// Ordered rewrites applied to responses coming back from an upstream
+#[derive(Debug, Default)]
+pub struct Transforms {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+enum Rule {
+    SetHeader(HeaderName, HeaderValue),
+    RemoveHeader(HeaderName),
+    Status(StatusCode),
+    SetJson(JsonPath, Value),
+    RemoveJson(JsonPath),
+    Replace(Regex, String),
+}
+
+impl Transforms {
+    pub fn compile(rules: &[TransformRule]) -> Result<Self, String> {
+        let rules = rules.iter().map(Rule::compile).collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub async fn apply(&self, response: Response<NoxBody>) -> Response<NoxBody> {
+        if self.rules.is_empty() {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        // Compressed bodies can't be edited without decoding them first
+        let encoded = parts
+            .headers
+            .get(CONTENT_ENCODING)
+            .is_some_and(|encoding| encoding != "identity");
+
+        if !self.rules.iter().any(Rule::edits_body) || encoded {
+            for rule in &self.rules {
+                rule.apply_head(&mut parts);
+            }
+            return Response::from_parts(parts, body);
+        }
+
+        let mut bytes = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(err) => {
+                eprintln!("Failed to read upstream response for transform: {}", err);
+                return Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(body::full(format!("Upstream response failed: {}", err)))
+                    .unwrap();
+            }
+        };
+        for rule in &self.rules {
+            rule.apply_head(&mut parts);
+            bytes = rule.apply_body(bytes);
+        }
+        // The body is sent in one piece now, so hyper sets the new length
+        parts.headers.remove(CONTENT_LENGTH);
+        Response::from_parts(parts, body::full(bytes))
+    }
+}
+
+impl Rule {
+    fn compile(rule: &TransformRule) -> Result<Self, String> {
+        let header_name = |name: &str| {
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("invalid header name '{}'", name))
+        };
+
+        Ok(match rule {
+            TransformRule::SetHeader { name, value } => Rule::SetHeader(
+                header_name(name)?,
+                HeaderValue::from_str(value).map_err(|_| format!("invalid value for header '{}'", name))?,
+            ),
+            TransformRule::RemoveHeader { name } => Rule::RemoveHeader(header_name(name)?),
+            TransformRule::Status { status } => Rule::Status(
+                StatusCode::from_u16(*status).map_err(|_| format!("invalid status code {}", status))?,
+            ),
+            TransformRule::SetJson { path, value } => Rule::SetJson(JsonPath::parse(path)?, value.clone()),
+            TransformRule::RemoveJson { path } => Rule::RemoveJson(JsonPath::parse(path)?),
+            TransformRule::Replace { pattern, with } => Rule::Replace(
+                Regex::new(pattern).map_err(|e| format!("invalid pattern '{}': {}", pattern, e))?,
+                with.clone(),
+            ),
+        })
+    }
+
+    fn edits_body(&self) -> bool {
+        matches!(self, Rule::SetJson(..) | Rule::RemoveJson(_) | Rule::Replace(..))
+    }
+
+    fn apply_head(&self, parts: &mut http::response::Parts) {
+        match self {
+            Rule::SetHeader(name, value) => {
+                parts.headers.insert(name.clone(), value.clone());
+            }
+            Rule::RemoveHeader(name) => {
+                parts.headers.remove(name);
+            }
+            Rule::Status(status) => parts.status = *status,
+            _ => {}
+        }
+    }
+
+    // Bodies a rule can't apply to (JSON rules on a non-JSON body, regex rules
+    // on binary data) pass through untouched
+    fn apply_body(&self, bytes: Bytes) -> Bytes {
+        match self {
+            Rule::SetJson(path, value) => edit_json(bytes, |json| path.set(json, value)),
+            Rule::RemoveJson(path) => edit_json(bytes, |json| path.remove(json)),
+            Rule::Replace(regex, with) => match std::str::from_utf8(&bytes) {
+                Ok(text) if regex.is_match(text) => Bytes::from(regex.replace_all(text, with.as_str()).into_owned()),
+                _ => bytes,
+            },
+            _ => bytes,
+        }
+    }
+}
+
+fn edit_json(bytes: Bytes, edit: impl FnOnce(&mut Value) -> usize) -> Bytes {
+    let Ok(mut json) = serde_json::from_slice::<Value>(&bytes) else {
+        return bytes;
+    };
+    if edit(&mut json) == 0 {
+        return bytes;
+    }
+    Bytes::from(json.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transforms(yaml: &str) -> Transforms {
+        Transforms::compile(&serde_yaml::from_str::<Vec<TransformRule>>(yaml).unwrap()).unwrap()
+    }
+
+    fn upstream_response(headers: &[(&str, &str)], body: &str) -> Response<NoxBody> {
+        let mut builder = Response::builder().status(200);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder
+            .header(CONTENT_LENGTH, body.len())
+            .body(body::full(body.to_string()))
+            .unwrap()
+    }
+
+    async fn body_text(response: Response<NoxBody>) -> String {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rules_apply_in_order() {
+        let first = transforms(
+            r#"
+- { action: replace, pattern: "a", with: "b" }
+- { action: replace, pattern: "b", with: "c" }
+"#,
+        );
+        let second = transforms(
+            r#"
+- { action: replace, pattern: "b", with: "c" }
+- { action: replace, pattern: "a", with: "b" }
+"#,
+        );
+        assert_eq!(body_text(first.apply(upstream_response(&[], "a")).await).await, "c");
+        assert_eq!(body_text(second.apply(upstream_response(&[], "a")).await).await, "b");
+    }
+
+    #[tokio::test]
+    async fn test_json_rules() {
+        let rules = transforms(
+            r#"
+- { action: set_json, path: "$.user.name", value: "redacted" }
+- { action: remove_json, path: "$.user.token" }
+- { action: set_json, path: "$.items[*].price", value: 0 }
+"#,
+        );
+        let body = r#"{"user": {"name": "ada", "token": "t0k3n"}, "items": [{"price": 5}, {"price": 7}]}"#;
+        let response = rules.apply(upstream_response(&[], body)).await;
+
+        let json: Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(json, json!({ "user": { "name": "redacted" }, "items": [{ "price": 0 }, { "price": 0 }] }));
+
+        // Non-JSON bodies pass through untouched
+        let response = rules.apply(upstream_response(&[], "plain text")).await;
+        assert_eq!(body_text(response).await, "plain text");
+    }
+
+    #[tokio::test]
+    async fn test_replace_with_groups() {
+        let rules = transforms(r#"[{ action: replace, pattern: "https://(\\w+)\\.internal", with: "http://$1.test" }]"#);
+        let response = rules.apply(upstream_response(&[], "see https://api.internal/x")).await;
+        assert_eq!(body_text(response).await, "see http://api.test/x");
+    }
+
+    #[tokio::test]
+    async fn test_body_edits_drop_content_length() {
+        let edit = transforms(r#"[{ action: replace, pattern: "short", with: "much longer" }]"#);
+        let response = edit.apply(upstream_response(&[], "short")).await;
+        assert!(response.headers().get(CONTENT_LENGTH).is_none());
+        assert_eq!(body_text(response).await, "much longer");
+
+        let head_only = transforms(
+            r#"
+- { action: set_header, name: x-mocked, value: "yes" }
+- { action: remove_header, name: server }
+- { action: status, status: 299 }
+"#,
+        );
+        let response = head_only.apply(upstream_response(&[("server", "upstream")], "short")).await;
+        assert_eq!(response.status().as_u16(), 299);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "5");
+        assert_eq!(response.headers()["x-mocked"], "yes");
+        assert!(response.headers().get("server").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_bodies_pass_through() {
+        let rules = transforms(
+            r#"
+- { action: replace, pattern: "secret", with: "hidden" }
+- { action: set_header, name: x-mocked, value: "yes" }
+"#,
+        );
+        let response = rules.apply(upstream_response(&[("content-encoding", "gzip")], "secret")).await;
+        assert_eq!(response.headers()[CONTENT_LENGTH], "6");
+        assert_eq!(response.headers()["x-mocked"], "yes");
+        assert_eq!(body_text(response).await, "secret");
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        for yaml in [
+            r#"[{ action: set_header, name: "bad header", value: x }]"#,
+            r#"[{ action: status, status: 42 }]"#,
+            r#"[{ action: replace, pattern: "(", with: x }]"#,
+            r#"[{ action: remove_json, path: "user" }]"#,
+        ] {
+            let rules: Vec<TransformRule> = serde_yaml::from_str(yaml).unwrap();
+            assert!(Transforms::compile(&rules).is_err(), "{}", yaml);
+        }
+    }
+}
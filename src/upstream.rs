@@ -0,0 +1,170 @@
+use crate::body::{self, NoxBody};
+use crate::context::{Overflow, RequestContext};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use http_body_util::{BodyExt, BodyStream, StreamBody};
+use hyper::body::Frame;
+use hyper::{Response, StatusCode};
+use std::fmt::{self, Display};
+use std::io;
+use std::time::Duration;
+
+pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Set on every response that came from an upstream rather than a mock, naming
+// the upstream it came from
+pub const UPSTREAM_HEADER: &str = "x-nox-upstream";
+
+// An HTTP client for upstreams. Connecting and waiting for the response
+// headers are each bounded by the timeout; the body isn't, so long
+// downloads and event streams relay for as long as they run
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    Request(reqwest::Error),
+    Timeout(Duration),
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Request(e) => write!(f, "{}", e),
+            SendError::Timeout(timeout) => write!(f, "no response after {}ms", timeout.as_millis()),
+        }
+    }
+}
+
+impl Client {
+    // Redirects are passed back to the client rather than followed, so the
+    // upstream is seen exactly as it answered
+    pub fn new(timeout: Duration) -> Self {
+        let http = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("failed to build HTTP client");
+        Self { http, timeout }
+    }
+
+    // Sends the request on to `url`, keeping the method, end-to-end headers
+    // and body. With an overflow, the buffered part of the body is followed
+    // by the rest as the client sends it
+    pub async fn send(
+        &self,
+        url: &str,
+        ctx: &RequestContext,
+        overflow: Option<Overflow>,
+    ) -> Result<reqwest::Response, SendError> {
+        let method = reqwest::Method::from_bytes(ctx.method.as_str().as_bytes()).unwrap_or(reqwest::Method::GET);
+        let body = match overflow {
+            Some(overflow) => {
+                let rest = BodyStream::new(overflow.into_inner())
+                    .try_filter_map(|frame| future::ready(Ok(frame.into_data().ok())));
+                reqwest::Body::wrap_stream(stream::once(future::ready(Ok(ctx.body.clone()))).chain(rest))
+            }
+            None => reqwest::Body::from(ctx.body.clone()),
+        };
+
+        let mut request = self.http.request(method, url).body(body);
+        for (name, value) in &ctx.headers {
+            if !is_hop_by_hop_header(name.as_str()) {
+                request = request.header(name.as_str(), value.as_bytes());
+            }
+        }
+        self.execute(request).await
+    }
+
+    pub async fn get(&self, url: &str) -> Result<reqwest::Response, SendError> {
+        self.execute(self.http.get(url)).await
+    }
+
+    async fn execute(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, SendError> {
+        match tokio::time::timeout(self.timeout, request.send()).await {
+            Ok(result) => result.map_err(SendError::Request),
+            Err(_) => Err(SendError::Timeout(self.timeout)),
+        }
+    }
+}
+
+// Joins the upstream base URL with the request's path and query
+pub fn target_url(base_url: &str, ctx: &RequestContext) -> String {
+    let path_and_query = ctx.uri.path_and_query().map_or("/", |pq| pq.as_str());
+    format!("{}{}", base_url.trim_end_matches('/'), path_and_query)
+}
+
+// The upstream's status and end-to-end headers on a new response builder,
+// marked with the upstream's name
+pub fn response_builder(upstream: &reqwest::Response, name: &str) -> http::response::Builder {
+    let mut builder = Response::builder()
+        .status(upstream.status().as_u16())
+        .header(UPSTREAM_HEADER, name);
+    for (name, value) in upstream.headers() {
+        if !is_hop_by_hop_header(name.as_str()) {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+    }
+    builder
+}
+
+// Relays the upstream response as it arrives rather than buffering it.
+// `guard` is held until the body has been sent or abandoned
+pub fn streamed<G: Send + Sync + 'static>(upstream: reqwest::Response, name: &str, guard: G) -> Response<NoxBody> {
+    let builder = response_builder(&upstream, name);
+    let frames = upstream.bytes_stream().map(move |chunk| {
+        let _ = &guard;
+        chunk
+            .map(Frame::data)
+            .map_err(|e| io::Error::other(e.to_string()))
+    });
+    builder.body(BodyExt::boxed(StreamBody::new(frames))).unwrap()
+}
+
+// Passes requests straight through to a single upstream
+pub struct Passthrough {
+    base_url: String,
+    client: Client,
+}
+
+impl Passthrough {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            client: Client::new(DEFAULT_UPSTREAM_TIMEOUT),
+        }
+    }
+
+    pub async fn forward(&self, ctx: &RequestContext, overflow: Option<Overflow>) -> Response<NoxBody> {
+        match self.client.send(&target_url(&self.base_url, ctx), ctx, overflow).await {
+            Ok(response) => streamed(response, &self.base_url, ()),
+            Err(err) => bad_gateway(&err),
+        }
+    }
+}
+
+pub fn bad_gateway(err: &dyn Display) -> Response<NoxBody> {
+    eprintln!("Upstream request failed: {}", err);
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(body::full(format!("Upstream request failed: {}", err)))
+        .unwrap()
+}
+
+// Headers that describe a single connection and must not be forwarded
+pub fn is_hop_by_hop_header(name: &str) -> bool {
+    matches!(
+        name.to_ascii_lowercase().as_str(),
+        "connection"
+            | "keep-alive"
+            | "proxy-authenticate"
+            | "proxy-authorization"
+            | "te"
+            | "trailers"
+            | "transfer-encoding"
+            | "upgrade"
+            | "host" // the client sets this for the upstream
+    )
+}
@@ -0,0 +1,117 @@
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Request, StatusCode};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use nox::server::NoxServer;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+async fn send(method: &str, url: &str, body: &str) -> (StatusCode, String) {
+    let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+    let request = Request::builder()
+        .method(method)
+        .uri(url)
+        .header("content-type", "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+#[tokio::test]
+async fn test_stub_matches_json_body() {
+    let server = NoxServer::start_ephemeral().await.unwrap();
+    let id = server
+        .stub("POST", "/users")
+        .body_json_path("$.user.id", 42)
+        .status(201)
+        .json(json!({ "created": true }))
+        .mount()
+        .unwrap();
+
+    let (status, body) = send("POST", &server.url("/users"), r#"{"user": {"id": 42}}"#).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), json!({ "created": true }));
+    server.assert_calls(&id, 1);
+
+    // A body the stub doesn't accept gets the 404 that explains why
+    let (status, body) = send("POST", &server.url("/users"), r#"{"user": {"id": 7}}"#).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["error"], "No route matched");
+    assert_eq!(body["candidates"][0]["id"], id.as_str());
+    assert!(body["candidates"][0]["reason"].as_str().unwrap().contains("$.user.id"), "{}", body);
+    server.assert_calls(&id, 1);
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_waits_for_in_flight_requests() {
+    let server = NoxServer::start_ephemeral().await.unwrap();
+    server.stub("GET", "/slow").body("done").delay(300).mount().unwrap();
+    let addr = server.addr();
+
+    let url = server.url("/slow");
+    let request = tokio::spawn(async move { send("GET", &url, "").await });
+    // The journal records a request before its delay starts
+    while server.requests().is_empty() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let started = Instant::now();
+    server.shutdown().await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert_eq!(request.await.unwrap(), (StatusCode::OK, "done".to_string()));
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_dropping_handle_stops_server() {
+    let server = NoxServer::start_ephemeral().await.unwrap();
+    let addr = server.addr();
+    let (status, _) = send("GET", &server.url("/health"), "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    drop(server);
+    // The server drains in the background, so give it a moment to close
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while TcpStream::connect(addr).await.is_ok() {
+        assert!(Instant::now() < deadline, "server still accepting after the handle was dropped");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(feature = "config")]
+#[tokio::test]
+async fn test_oversized_body_skips_mocks() {
+    let config: nox::config::NoxConfig = serde_yaml::from_str(
+        r#"
+server: { host: 127.0.0.1, port: 0 }
+mock:
+  max_body_size: 10
+  scenarios:
+    - name: uploads
+      routes:
+        - { path: /upload, method: POST, body: { contains: abc }, response: { status: 200, body: matched } }
+"#,
+    )
+    .unwrap();
+    let server = NoxServer::from_config(&config).start().await.unwrap();
+
+    let (status, body) = send("POST", &server.url("/upload"), "abc").await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "matched"));
+
+    // The first bytes would match, but the body as a whole was never seen
+    let (status, _) = send("POST", &server.url("/upload"), "abcdefghijklmnopqrstuvwxyz").await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].route_id, None);
+
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,111 @@
+#![cfg(all(feature = "proxy", feature = "config"))]
+
+use nox::config::NoxConfig;
+use nox::server::NoxServer;
+
+// A proxy on an ephemeral port in front of `upstream`, retrying twice and
+// opening the circuit after four failures. Ejection is set out of reach so
+// only the breaker takes the upstream out
+async fn start_proxy(upstream: &str) -> nox::harness::NoxHandle {
+    let yaml = format!(
+        r#"
+server: {{ host: 127.0.0.1, port: 0 }}
+mock:
+  scenarios: []
+proxy:
+  max_fails: 100
+  retry: {{ attempts: 2, backoff: 1 }}
+  circuit_breaker: {{ failure_threshold: 4, open_timeout: 60 }}
+  routes:
+    - prefix: /api
+      upstreams:
+        - {{ name: flaky, url: "{}" }}
+"#,
+        upstream
+    );
+    let config: NoxConfig = serde_yaml::from_str(&yaml).unwrap();
+    NoxServer::from_config(&config).start().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_retries_then_opens_circuit() {
+    let upstream = NoxServer::start_ephemeral().await.unwrap();
+    upstream.stub("GET", "/api/flaky").id("get").status(503).mount().unwrap();
+    upstream.stub("POST", "/api/flaky").id("post").status(503).mount().unwrap();
+
+    let proxy = start_proxy(upstream.base_url()).await;
+    let client = reqwest::Client::new();
+
+    let health = client.get(proxy.url("/__nox/health")).send().await.unwrap();
+    let health: serde_json::Value = health.json().await.unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["upstreams"][0]["circuit"], "closed");
+
+    // A GET is tried once and retried twice, then the upstream's 503 is passed on
+    let response = client.get(proxy.url("/api/flaky")).send().await.unwrap();
+    assert_eq!(response.status(), 503);
+    upstream.assert_calls("get", 3);
+
+    // A POST isn't idempotent, so it gets a single try. That is the fourth
+    // failure in a row, which opens the circuit
+    let response = client.post(proxy.url("/api/flaky")).body("{}").send().await.unwrap();
+    assert_eq!(response.status(), 503);
+    upstream.assert_calls("post", 1);
+
+    let response = client.get(proxy.url("/api/flaky")).send().await.unwrap();
+    assert_eq!(response.status(), 503);
+    assert!(response.text().await.unwrap().starts_with("No healthy upstream"));
+    upstream.assert_calls("get", 3);
+
+    let health = client.get(proxy.url("/__nox/health")).send().await.unwrap();
+    assert_eq!(health.status(), 200);
+    let health: serde_json::Value = health.json().await.unwrap();
+    assert_eq!(health["status"], "degraded");
+    assert_eq!(health["upstreams"][0]["name"], "flaky");
+    assert_eq!(health["upstreams"][0]["circuit"], "open");
+    assert_eq!(health["upstreams"][0]["available"], false);
+    assert_eq!(health["upstreams"][0]["healthy"], true);
+
+    proxy.shutdown().await.unwrap();
+    upstream.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_spa_fallback_leaves_proxied_paths_alone() {
+    let upstream = NoxServer::start_ephemeral().await.unwrap();
+    upstream.stub("GET", "/api/users").id("users").body("[]").mount().unwrap();
+
+    let dist = tempfile::tempdir().unwrap();
+    std::fs::write(dist.path().join("index.html"), "<html>spa</html>").unwrap();
+    let yaml = format!(
+        r#"
+server: {{ host: 127.0.0.1, port: 0 }}
+mock:
+  scenarios: []
+static_files:
+  - {{ prefix: /, root_dir: "{}", spa_fallback: index.html }}
+proxy:
+  routes:
+    - prefix: /api
+      upstreams:
+        - {{ name: api, url: "{}" }}
+"#,
+        dist.path().display(),
+        upstream.base_url()
+    );
+    let config: NoxConfig = serde_yaml::from_str(&yaml).unwrap();
+    let proxy = NoxServer::from_config(&config).start().await.unwrap();
+    let client = reqwest::Client::new();
+
+    let response = client.get(proxy.url("/api/users")).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "[]");
+    upstream.assert_calls("users", 1);
+
+    let response = client.get(proxy.url("/users/42")).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "<html>spa</html>");
+
+    proxy.shutdown().await.unwrap();
+    upstream.shutdown().await.unwrap();
+}